@@ -11,8 +11,17 @@ fn main() {
 
     let builder = flif::dec::decode(&mut file).unwrap();
 
-    println!("{:#?}", builder.info());
+    let info = builder.info();
+    println!("{}x{}, {} channels with {} bits, {:?}, {:?}",
+        info.width(), info.height(), info.num_channels(), info.bit_depth(), info.encoding(), info.movement());
+    println!("{} frames, loops: {:?}, alpha_zero: {}, metadata: {:?}",
+        info.num_frames(), info.num_loops(), info.alpha_zero(), info.metadata());
+    println!("transformations: {:?}", info.transformations());
 
-    flif::dec::decode_image(builder, Default::default()).unwrap();
+    let images = flif::dec::decode_image(builder, Default::default()).unwrap();
 
+    for (i, image) in images.iter().enumerate() {
+        println!("frame {}: {}x{}, {:?} with {} bits, delay: {:?}",
+            i, image.width(), image.height(), image.color_type(), image.bit_depth(), image.delay());
+    }
 }
@@ -0,0 +1,76 @@
+use std::fmt::Debug;
+use frame::ColorVal;
+
+/// The ranges the values of each plane can lie in.
+///
+/// Transformations wrap the ranges of the previous step, so the ranges
+/// can get narrower (and depend on the values of the previous planes)
+/// the more transformations are applied.
+pub trait ColorRanges: Debug {
+    fn num_planes(&self) -> usize;
+    fn min(&self, p: usize) -> ColorVal;
+    fn max(&self, p: usize) -> ColorVal;
+
+    /// The range of plane `p` given the values of the previous planes
+    fn minmax(&self, p: usize, _prev: &[ColorVal]) -> (ColorVal, ColorVal) {
+        (self.min(p), self.max(p))
+    }
+
+    /// The range of plane `p` and the `guess` snapped into that range
+    fn snap(&self, p: usize, prev: &[ColorVal], guess: ColorVal) -> (ColorVal, ColorVal, ColorVal) {
+        let (min, mut max) = self.minmax(p, prev);
+        if min > max {
+            warn!("Corruption detected: empty range in plane {}", p);
+            max = min;
+        }
+
+        (min, max, clamp(guess, min, max))
+    }
+
+    /// Whether the ranges are independent of the values of previous planes
+    fn is_static(&self) -> bool {
+        false
+    }
+}
+
+/// Fixed ranges for every plane
+#[derive(Debug,Clone)]
+pub struct StaticColorRanges {
+    ranges: Vec<(ColorVal, ColorVal)>,
+}
+
+impl StaticColorRanges {
+    pub fn new(ranges: Vec<(ColorVal, ColorVal)>) -> Self {
+        StaticColorRanges {
+            ranges: ranges,
+        }
+    }
+}
+
+impl ColorRanges for StaticColorRanges {
+    fn num_planes(&self) -> usize {
+        self.ranges.len()
+    }
+
+    fn min(&self, p: usize) -> ColorVal {
+        self.ranges[p].0
+    }
+
+    fn max(&self, p: usize) -> ColorVal {
+        self.ranges[p].1
+    }
+
+    fn is_static(&self) -> bool {
+        true
+    }
+}
+
+pub fn clamp(value: ColorVal, min: ColorVal, max: ColorVal) -> ColorVal {
+    if value > max {
+        max
+    } else if value < min {
+        min
+    } else {
+        value
+    }
+}
@@ -1,12 +1,29 @@
+use std::{cmp, mem};
 use std::io::{self, Read};
 use podio::ReadPodExt;
 use varint::{self, ReadVarintExt};
-use format::{Format, Encoding};
-use metadata::{self, Metadata};
+use format::Format;
+use metadata::{self, Metadata, MetadataOptions};
+use exif::{self, Orientation};
 use maniac::{rac, symbol, UniformSymbolDecoder};
-use image::Image;
+use maniac::chance::ChanceTable;
+use image::{Image, Movement};
+use frame::{ColorVal, Frame};
+use colors::{ColorRanges, StaticColorRanges};
+use transform::{self, Transform};
+use pixels;
+
+pub use format::Encoding;
+pub use transform::Transformation;
+
+pub fn decode<R: Read>(r: R) -> Result<ImageDecoderBuilder<R>, Error> {
+    decode_with_options(r, DecoderOptions::default())
+}
 
-pub fn decode<R: Read>(mut r: R) -> Result<ImageDecoderBuilder<R>, Error> {
+/// Reads the header of the image.
+/// Only the limits, the metadata options and `apply_orientation` of `options`
+/// are used at this point.
+pub fn decode_with_options<R: Read>(mut r: R, options: DecoderOptions) -> Result<ImageDecoderBuilder<R>, Error> {
     // Read the magic
     let mut buf: [u8; 4] = [0; 4];
     r.read_exact(&mut buf)?;
@@ -31,7 +48,12 @@ pub fn decode<R: Read>(mut r: R) -> Result<ImageDecoderBuilder<R>, Error> {
         1
     };
 
-    let metadata = Metadata::all_from_reader(&mut r)?;
+    let limits = metadata::Limits {
+        chunk: options.max_metadata_chunk_size,
+        total: options.max_metadata_size,
+    };
+    let metadata = Metadata::all_from_reader(&mut r, &options.metadata, &limits)?;
+    let orientation = exif_orientation(&metadata, &limits, options.apply_orientation)?;
 
     let rac = rac::Input24::new(r)?;
     let mut meta_decoder = symbol::UniformSymbolDecoder::new(rac);
@@ -61,24 +83,121 @@ pub fn decode<R: Read>(mut r: R) -> Result<ImageDecoderBuilder<R>, Error> {
         None
     };
 
+    if n_frames > options.max_frames {
+        return Err(Error::FrameLimitExceeded);
+    }
+
+    // The frames already keep some data per row, so their size has to be
+    // checked before they are created
+    match planes_size(width, height, n_frames, format.num_planes as u64) {
+        Some(size) if size <= options.max_image_buffer_size => {}
+        _ => return Err(Error::BufferSizeExceedsLimit),
+    }
+
+    let mut frames = Vec::new();
+    for frame_i in 0..n_frames {
+        let delay = if n_frames > 1 {
+            trace!("Decoding delay for frame {}", frame_i);
+            Some(meta_decoder.read_int(0, 60_000)? as u16)
+        } else {
+            None
+        };
+        debug!("delay of frame {}: {:?}", frame_i, delay);
+
+        frames.push(Frame::new(width as usize, height as usize, delay));
+    }
+
+    let mut cutoff: u8 = 2;
+    let mut alpha = u32::MAX / 19;
+
+    if meta_decoder.read_bool()? {
+        cutoff = meta_decoder.read_int(1, 128)? as u8;
+        alpha = u32::MAX / meta_decoder.read_int(2, 128)? as u32;
+        // FLIF16 reserves this flag but does not define how a custom
+        // table of initial chances is stored, so there is no way to read it.
+        // The reference decoder refuses these files as well.
+        if meta_decoder.read_bool()? {
+            return Err(Error::CustomBitchance);
+        }
+    }
+
+    debug!("cutoff = {}", cutoff);
+    debug!("alpha = {}", alpha);
+
+    let max_value = (1 << highest_bpp) - 1;
+    let ranges = StaticColorRanges::new(vec![(0, max_value); format.num_planes as usize]);
+    let header = transform::Header {
+        alpha_zero: alpha_zero,
+    };
+    let chain = transform::read_chain(&mut meta_decoder, Box::new(ranges), &header, &mut frames)?;
+
     Ok(ImageDecoderBuilder {
         meta_decoder: meta_decoder,
+        frames: frames,
+        ranges: chain.ranges,
+        cutoff: cutoff,
+        alpha: alpha,
+        preview: None,
         info: Info {
             width: width,
             height: height,
             highest_bpp: highest_bpp,
             n_frames: n_frames,
             encoding: format.encoding,
+            movement: if format.is_animated { Movement::Animated } else { Movement::Static },
             alpha_zero: alpha_zero,
             metadata: metadata,
             n_channels: format.num_planes,
             n_loops: n_loops,
+            transforms: chain.transforms,
+            orientation: orientation,
         }
     })
 }
 
+/// The orientation given by the EXIF metadata, `Normal` without any.
+/// EXIF that can't be read is only an error if the orientation is going to be applied.
+/// The inflated copy counts against the total limit, together with the chunks that are kept.
+fn exif_orientation(metadata: &[Metadata], limits: &metadata::Limits, apply: bool) -> Result<Orientation, Error> {
+    let chunk = match metadata.iter().find(|metadata| metadata.format == metadata::Format::Exif) {
+        Some(chunk) => chunk,
+        None => return Ok(Orientation::Normal),
+    };
+
+    let used = metadata.iter().map(|metadata| metadata.data.len() as u64).sum();
+    let orientation = chunk.inflate_within(limits, used)
+        .map_err(Error::from)
+        .and_then(|data| exif::orientation(&data).map_err(Error::from));
+
+    match orientation {
+        Ok(orientation) => Ok(orientation.unwrap_or_default()),
+        Err(err) if apply => Err(err),
+        Err(err) => {
+            warn!("Ignoring unreadable EXIF orientation: {}", err);
+            Ok(Orientation::Normal)
+        }
+    }
+}
+
+/// The memory taken by the planes of all frames while they are decoded,
+/// `None` if it doesn't even fit into a `u64`
+fn planes_size(width: u64, height: u64, n_frames: u64, n_planes: u64) -> Option<u64> {
+    width.checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(n_frames))
+        .and_then(|pixels| pixels.checked_mul(n_planes))
+        .and_then(|samples| samples.checked_mul(mem::size_of::<ColorVal>() as u64))
+}
+
+/// Receives the zoomlevel and the frames of a preview
+pub type PreviewCallback = Box<dyn FnMut(usize, &[Image])>;
+
 pub struct ImageDecoderBuilder<R> {
     meta_decoder: UniformSymbolDecoder<rac::Config24, R>,
+    frames: Vec<Frame>,
+    ranges: Box<dyn ColorRanges>,
+    cutoff: u8,
+    alpha: u32,
+    preview: Option<PreviewCallback>,
     info: Info,
 }
 
@@ -86,14 +205,44 @@ impl<R> ImageDecoderBuilder<R> {
     pub fn info(&self) -> &Info {
         &self.info
     }
+
+    /// Calls `callback` with a preview of all frames whenever an interlaced
+    /// image has been decoded down to another zoomlevel (0 being the full image,
+    /// which is not previewed). The previews are upscaled to the full size.
+    /// Non-interlaced images don't have previews.
+    pub fn on_preview<F>(mut self, callback: F) -> Self
+        where F: FnMut(usize, &[Image]) + 'static
+    {
+        self.preview = Some(Box::new(callback));
+        self
+    }
+}
+
+pub fn decode_image<R: Read>(builder: ImageDecoderBuilder<R>, options: DecoderOptions) -> Result<Vec<Image>, Error> {
+    decode_image_with_progress(builder, options).map(|(images, _)| images)
 }
 
-pub fn decode_image<R: Read>(builder: ImageDecoderBuilder<R>, options: DecoderOptions) -> Result<(), Error> {
+/// Like `decode_image`, but also tells how much of the image was present.
+/// Only files that are truncated have less than `Progress::Complete`, and
+/// they are only decoded with `DecoderOptions::allow_truncated`.
+pub fn decode_image_with_progress<R: Read>(builder: ImageDecoderBuilder<R>, options: DecoderOptions) -> Result<(Vec<Image>, Progress), Error> {
     let info = builder.info;
+    let mut frames = builder.frames;
+    let ranges = builder.ranges;
     let mut meta_decoder = builder.meta_decoder;
     let width = info.width;
     let height = info.height;
-    let resize_dimensions = options.resize_dimensions;
+    let orientation = if options.apply_orientation {
+        info.orientation
+    } else {
+        Orientation::Normal
+    };
+    debug!("orientation = {:?}", orientation);
+
+    // The resize dimensions are meant for the oriented image
+    let resize_dimensions = options.resize_dimensions.map(|(w, h)| {
+        if orientation.is_transposing() { (h, w) } else { (w, h) }
+    });
     let (mut resize_w, mut resize_h) = resize_dimensions.unwrap_or((width, height));
 
     let (mut target_w, mut target_h) = (resize_w, resize_h);
@@ -114,6 +263,18 @@ pub fn decode_image<R: Read>(builder: ImageDecoderBuilder<R>, options: DecoderOp
             target_h = height;
         }
     }
+    debug!("target dimensions = {}x{}", target_w, target_h);
+
+    // Shrink the image into the target dimensions, keeping its aspect ratio
+    let fit_dimensions = if options.fit {
+        let factor = f64::min(target_w as f64 / width as f64, target_h as f64 / height as f64);
+        let fit_w = cmp::max(1, (width as f64 * factor).round() as usize);
+        let fit_h = cmp::max(1, (height as f64 * factor).round() as usize);
+        debug!("fit dimensions = {}x{}", fit_w, fit_h);
+        Some((fit_w, fit_h))
+    } else {
+        None
+    };
 
     // Find a fitting downscale factor if resize dimensions are set
     let mut scale: u64 = options.scale_down.into();
@@ -146,54 +307,131 @@ pub fn decode_image<R: Read>(builder: ImageDecoderBuilder<R>, options: DecoderOp
         debug!("Decoding downscaled image at scale 1:{} ({}x{} -> {}x{})", scale, width, height, new_width, new_height);
     }
 
-    // Estimate buffer size
-    let n_channels = info.n_channels as u64;
-    let bytes_per_pixel_per_channel = if info.highest_bpp <= 8 { 1 } else { 2 };
-    let additional_bytes = if n_channels > 1 { 2 } else { 0 };
-    let bytes_per_pixel: u64 = bytes_per_pixel_per_channel * (n_channels + additional_bytes);
+    // The planes are decoded at full resolution whatever the scale,
+    // as every zoomlevel is predicted from the one before
     let n_frames = info.n_frames;
-    let estimated_buffer_size: u64 = (((width-1)/scale)+1) * (((height-1)/scale)+1) * n_frames * n_channels * bytes_per_pixel;
-    debug!("estimated_buffer_size = {}", estimated_buffer_size);
+    let estimated_buffer_size = planes_size(width, height, n_frames, ranges.num_planes() as u64);
+    debug!("estimated_buffer_size = {:?}", estimated_buffer_size);
 
-    if estimated_buffer_size > options.max_image_buffer_size {
-        return Err(Error::BufferSizeExceedsLimit);
+    match estimated_buffer_size {
+        Some(size) if size <= options.max_image_buffer_size => {}
+        _ => return Err(Error::BufferSizeExceedsLimit),
     }
 
     if n_frames > options.max_frames {
         return Err(Error::FrameLimitExceeded);
     }
 
-    let mut images = Vec::new();
-    for frame_i in 0..n_frames {
-        let delay = if info.n_frames > 1 {
-            trace!("Decoding delay for frame {}", frame_i);
-            Some(meta_decoder.read_int(0, 60_000)? as u16)
-        } else {
-            None
-        };
-        debug!("delay of frame {}: {:?}", frame_i, delay);
+    for frame in &mut frames {
+        frame.allocate_planes(ranges.num_planes());
+    }
 
-        let image = Image::new(width, height, delay);
-        images.push(image);
+    // Planes without any freedom are not stored at all
+    for p in 0..ranges.num_planes() {
+        if ranges.min(p) >= ranges.max(p) {
+            for frame in &mut frames {
+                frame.fill_plane(p, ranges.min(p));
+            }
+        }
     }
 
-    let mut cutoff: u8 = 2;
-    let mut alpha = u32::max_value() / 19;
+    let chance_table = ChanceTable::new(builder.cutoff, builder.alpha);
+    let bit_depth = info.highest_bpp;
+    let transforms = &info.transforms;
+    let mut preview = builder.preview.map(|mut callback| move |zoomlevel, mut frames: Vec<Frame>| {
+        for frame in &mut frames {
+            frame.downscale(scale as usize);
+        }
+        for transform in transforms.iter().rev() {
+            transform.undo(&mut frames);
+        }
+        if let Some((fit_w, fit_h)) = fit_dimensions {
+            for frame in &mut frames {
+                frame.resample(fit_w, fit_h);
+            }
+        }
+        for frame in &mut frames {
+            frame.orient(orientation);
+        }
+        let images: Vec<Image> = frames.into_iter().map(|frame| frame.into_image(bit_depth)).collect();
+        callback(zoomlevel, &images);
+    });
+
+    let progress = match info.encoding {
+        Encoding::NonInterlaced => {
+            match pixels::decode_scanlines(meta_decoder.rac(), &mut frames, &*ranges, &chance_table, info.alpha_zero)? {
+                Some((plane, row)) => Progress::Incomplete { plane: plane, row: row },
+                None => Progress::Complete,
+            }
+        }
+        Encoding::Interlaced => {
+            let preview = preview.as_mut().map(|preview| preview as pixels::Preview);
+            // Every zoomlevel halves either the rows or the columns
+            let end_zoomlevel = 2 * scale_shift as usize;
+            let zoomlevel = pixels::decode_interlaced(&mut meta_decoder, &mut frames, &*ranges, &chance_table, info.alpha_zero, end_zoomlevel, preview)?;
+            if zoomlevel > end_zoomlevel {
+                Progress::Zoomlevel(zoomlevel)
+            } else {
+                Progress::Complete
+            }
+        }
+    };
 
-    if meta_decoder.read_bool()? {
-        cutoff = meta_decoder.read_int(1, 128)? as u8;
-        alpha = u32::max_value() / meta_decoder.read_int(2, 128)? as u32;
-        if meta_decoder.read_bool()? {
-            return Err(Error::Unimplemented("non-default bitchance"));
+    if progress != Progress::Complete {
+        debug!("truncated file: {:?}", progress);
+        if !options.allow_truncated {
+            return Err(Error::Truncated);
         }
     }
 
-    debug!("cutoff = {}", cutoff);
-    debug!("alpha = {}", alpha);
+    if scale > 1 {
+        for frame in &mut frames {
+            frame.downscale(scale as usize);
+        }
+    }
+
+    for transform in info.transforms.iter().rev() {
+        transform.undo(&mut frames);
+    }
+
+    // Resampling the original colors is the same as resampling luma and
+    // chroma, because YCoCg is linear. Palette indices can't be resampled.
+    if let Some((fit_w, fit_h)) = fit_dimensions {
+        for frame in &mut frames {
+            frame.resample(fit_w, fit_h);
+        }
+    }
 
-    unimplemented!()
+    if orientation != Orientation::Normal {
+        for frame in &mut frames {
+            frame.orient(orientation);
+        }
+    }
+
+    Ok((frames.into_iter().map(|frame| frame.into_image(bit_depth)).collect(), progress))
+}
+
+/// How much of the pixel data could be decoded
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum Progress {
+    /// All of it
+    Complete,
+    /// The interlaced image ended early. It was decoded down to this zoomlevel
+    /// and upscaled from there. Every zoomlevel halves the rows or the columns
+    /// of the next one, 0 being the full resolution.
+    Zoomlevel(usize),
+    /// The non-interlaced image ended early, in this row of this plane.
+    /// Planes are numbered after the transformations, e.g. luma and chroma
+    /// after YCoCg, and are decoded in the order lookback, alpha, 0, 1, 2.
+    /// The rest of that row is garbage, and everything after it is set to
+    /// the lowest value of its plane.
+    Incomplete {
+        plane: usize,
+        row: usize,
+    },
 }
 
+/// What the header tells about the image
 #[derive(Debug)]
 pub struct Info {
     width: u64,
@@ -201,10 +439,98 @@ pub struct Info {
     highest_bpp: u8,
     n_frames: u64,
     encoding: Encoding,
+    movement: Movement,
     alpha_zero: bool,
     metadata: Vec<Metadata>,
     n_channels: u8,
     n_loops: Option<u8>,
+    transforms: Vec<Box<dyn Transform>>,
+    orientation: Orientation,
+}
+
+impl Info {
+    pub fn width(&self) -> u64 {
+        self.width
+    }
+
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// The number of channels: 1 (gray), 3 (RGB) or 4 (RGBA)
+    pub fn num_channels(&self) -> u8 {
+        self.n_channels
+    }
+
+    /// The number of bits per sample of the deepest channel, up to 16
+    pub fn bit_depth(&self) -> u8 {
+        self.highest_bpp
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn movement(&self) -> Movement {
+        self.movement
+    }
+
+    /// The number of frames, 1 for still images
+    pub fn num_frames(&self) -> u64 {
+        self.n_frames
+    }
+
+    /// How often an animation is played, 0 meaning forever.
+    /// Still images don't have a loop count.
+    pub fn num_loops(&self) -> Option<u8> {
+        self.n_loops
+    }
+
+    /// Whether the colors of fully transparent pixels were thrown away
+    pub fn alpha_zero(&self) -> bool {
+        self.alpha_zero
+    }
+
+    /// The metadata chunks in the order they are stored
+    pub fn metadata(&self) -> &[Metadata] {
+        &self.metadata
+    }
+
+    /// The contents of the first metadata chunk of the given kind,
+    /// if it has been inflated
+    pub fn metadata_of(&self, format: metadata::Format) -> Option<&[u8]> {
+        self.metadata.iter()
+            .find(|metadata| metadata.format == format && metadata.inflated)
+            .map(|metadata| &metadata.data[..])
+    }
+
+    /// The orientation the EXIF metadata gives, `Normal` without one.
+    /// Only applied to the decoded frames with `DecoderOptions::apply_orientation`.
+    /// EXIF chunks skipped by `DecoderOptions::metadata` are not read, and
+    /// neither are unreadable ones unless the orientation is applied.
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    /// The ICC color profile
+    pub fn icc_profile(&self) -> Option<&[u8]> {
+        self.metadata_of(metadata::Format::Icc)
+    }
+
+    /// The EXIF data, which `exif::Exif::parse` reads the tags of
+    pub fn exif(&self) -> Option<&[u8]> {
+        self.metadata_of(metadata::Format::Exif)
+    }
+
+    /// The XMP document
+    pub fn xmp(&self) -> Option<&[u8]> {
+        self.metadata_of(metadata::Format::Xmp)
+    }
+
+    /// The transformations the image was encoded with, in the order they were applied
+    pub fn transformations(&self) -> Vec<Transformation> {
+        self.transforms.iter().map(|transform| transform.kind()).collect()
+    }
 }
 
 quick_error! {
@@ -235,8 +561,11 @@ quick_error! {
         FrameLimitExceeded {
             description("Maximum number of frames exceeded")
         }
-        Unimplemented(err: &'static str) {
-            from()
+        CustomBitchance {
+            description("Custom initial bit chances are not defined by FLIF16")
+        }
+        Truncated {
+            description("The file ended before the image was complete")
         }
         Format(err: ::format::Error) {
             from()
@@ -244,6 +573,15 @@ quick_error! {
         Metadata(err: metadata::Error) {
             from()
         }
+        Exif(err: exif::Error) {
+            from()
+        }
+        Transform(err: transform::Error) {
+            from()
+        }
+        Pixels(err: pixels::Error) {
+            from()
+        }
         Rac(err: rac::Error) {
             from()
         }
@@ -266,13 +604,35 @@ pub struct DecoderOptions {
     pub fit: bool,
     /// Maximum image buffer size to attempt to decode.
     /// Default: 5GB
-    /// This is one frame of 1000 megapixels 8-bit RGB (it's 5 bytes per pixel
-    /// because YCoCg uses 2 bytes each for Co/Cg)
-    /// (or 1000 frames of 1 megapixel)
+    /// Every plane takes 4 bytes per pixel at full resolution while decoding,
+    /// even when scaling down. This is one frame of about 450 megapixels RGB
+    /// (or 450 frames of 1 megapixel)
     pub max_image_buffer_size: u64,
     /// Maximum number of frames to decode.
     /// Default: 50_000
     pub max_frames: u64,
+    /// Decode truncated files as far as they go instead of failing,
+    /// like the reference decoder does.
+    /// Default: false
+    pub allow_truncated: bool,
+    /// Which metadata chunks to skip, inflate or keep compressed.
+    /// Default: inflate all of them
+    pub metadata: MetadataOptions,
+    /// Maximum size of a single metadata chunk as it is kept in memory
+    /// (after inflating it, if it is inflated).
+    /// Default: 5MB
+    pub max_metadata_chunk_size: u64,
+    /// Maximum size of all metadata chunks together, including the copy of
+    /// compressed EXIF that is inflated for `apply_orientation`.
+    /// Default: 15MB
+    pub max_metadata_size: u64,
+    /// Turn the frames as the EXIF orientation says.
+    /// `resize_dimensions` are meant for the turned frames then.
+    /// This has to be set when reading the header already: EXIF that can't
+    /// be inflated or parsed is an error then instead of being ignored.
+    /// With `metadata.exif` set to `ChunkMode::Skip`, nothing is turned.
+    /// Default: false
+    pub apply_orientation: bool,
 }
 
 impl Default for DecoderOptions {
@@ -283,6 +643,11 @@ impl Default for DecoderOptions {
             fit: false,
             max_image_buffer_size: 5 * 1024 * 1024 * 1024,
             max_frames: 50_000,
+            allow_truncated: false,
+            metadata: MetadataOptions::default(),
+            max_metadata_chunk_size: 5 * 1024 * 1024,
+            max_metadata_size: 15 * 1024 * 1024,
+            apply_orientation: false,
         }
     }
 }
@@ -0,0 +1,514 @@
+/// How the stored image has to be transformed to be displayed upright,
+/// as given by the EXIF orientation tag
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Default)]
+pub enum Orientation {
+    #[default]
+    Normal,
+    FlipHorizontal,
+    Rotate180,
+    FlipVertical,
+    /// Mirrored along the top left to bottom right diagonal
+    Transpose,
+    /// Rotated clockwise by 90 degrees
+    Rotate90,
+    /// Mirrored along the top right to bottom left diagonal
+    Transverse,
+    /// Rotated clockwise by 270 degrees
+    Rotate270,
+}
+
+impl Orientation {
+    pub fn from_value(value: u16) -> Option<Self> {
+        Some(match value {
+            1 => Orientation::Normal,
+            2 => Orientation::FlipHorizontal,
+            3 => Orientation::Rotate180,
+            4 => Orientation::FlipVertical,
+            5 => Orientation::Transpose,
+            6 => Orientation::Rotate90,
+            7 => Orientation::Transverse,
+            8 => Orientation::Rotate270,
+            _ => return None,
+        })
+    }
+
+    /// Whether width and height are swapped
+    pub fn is_transposing(&self) -> bool {
+        matches!(*self, Orientation::Transpose | Orientation::Rotate90 | Orientation::Transverse | Orientation::Rotate270)
+    }
+
+    /// Where the pixel at `(x, y)` of a `width`x`height` image ends up
+    pub fn apply(&self, x: usize, y: usize, width: usize, height: usize) -> (usize, usize) {
+        let (right, bottom) = (width - 1, height - 1);
+        match *self {
+            Orientation::Normal => (x, y),
+            Orientation::FlipHorizontal => (right - x, y),
+            Orientation::Rotate180 => (right - x, bottom - y),
+            Orientation::FlipVertical => (x, bottom - y),
+            Orientation::Transpose => (y, x),
+            Orientation::Rotate90 => (bottom - y, x),
+            Orientation::Transverse => (bottom - y, right - x),
+            Orientation::Rotate270 => (y, right - x),
+        }
+    }
+}
+
+/// Finds the orientation tag in the EXIF data.
+/// Only the directory of the image itself is read.
+pub fn orientation(data: &[u8]) -> Result<Option<Orientation>, Error> {
+    let tiff = Tiff::new(data)?;
+    let mut entries = Vec::new();
+    tiff.read_ifd(tiff.u32(4)? as usize, Ifd::Primary, &mut entries, &mut Vec::new())?;
+
+    let exif = Exif {
+        entries: entries,
+    };
+    Ok(exif.orientation())
+}
+
+/// The directory a tag was found in
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum Ifd {
+    /// The tags of the image itself (IFD0)
+    Primary,
+    /// The tags of the thumbnail (IFD1)
+    Thumbnail,
+    /// The EXIF specific tags, like exposure and capture time
+    Exif,
+    Gps,
+    Interoperability,
+}
+
+/// The value of a tag, which is always a list of its type
+#[derive(Debug,Clone,PartialEq)]
+pub enum Value {
+    Byte(Vec<u8>),
+    /// Text without the terminating zeros
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    /// Numerator and denominator
+    Rational(Vec<(u32, u32)>),
+    SByte(Vec<i8>),
+    Undefined(Vec<u8>),
+    SShort(Vec<i16>),
+    SLong(Vec<i32>),
+    SRational(Vec<(i32, i32)>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::Ascii(ref text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The first value of an unsigned integer type
+    pub fn as_u32(&self) -> Option<u32> {
+        match *self {
+            Value::Byte(ref values) => values.first().map(|&v| v as u32),
+            Value::Short(ref values) => values.first().map(|&v| v as u32),
+            Value::Long(ref values) => values.first().cloned(),
+            _ => None,
+        }
+    }
+
+    /// All values of a rational or floating point type
+    pub fn as_f64s(&self) -> Option<Vec<f64>> {
+        Some(match *self {
+            Value::Rational(ref values) => values.iter().map(|&(n, d)| n as f64 / d as f64).collect(),
+            Value::SRational(ref values) => values.iter().map(|&(n, d)| n as f64 / d as f64).collect(),
+            Value::Float(ref values) => values.iter().map(|&v| v as f64).collect(),
+            Value::Double(ref values) => values.clone(),
+            _ => return None,
+        })
+    }
+}
+
+/// A tag and its value
+#[derive(Debug,Clone,PartialEq)]
+pub struct Entry {
+    pub ifd: Ifd,
+    pub tag: u16,
+    pub value: Value,
+}
+
+/// Position where a photo was taken
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub struct GpsPosition {
+    /// Degrees north, negative for south
+    pub latitude: f64,
+    /// Degrees east, negative for west
+    pub longitude: f64,
+    /// Meters above sea level, negative below
+    pub altitude: Option<f64>,
+}
+
+/// The tags of EXIF data
+#[derive(Debug,Clone,PartialEq)]
+pub struct Exif {
+    entries: Vec<Entry>,
+}
+
+impl Exif {
+    /// Reads the tags of the image and its thumbnail, including the ones
+    /// in the EXIF, GPS and interoperability directories.
+    /// `data` may start with the `Exif\0\0` prefix of JPEG's APP1 segments.
+    /// Entries with unknown types are left out, as are entries and directories
+    /// that can't be read. Only a broken header or IFD0 is an error.
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let tiff = Tiff::new(data)?;
+        let mut entries = Vec::new();
+        let mut visited = Vec::new();
+
+        let first = tiff.u32(4)? as usize;
+        let next = tiff.read_ifd(first, Ifd::Primary, &mut entries, &mut visited)?;
+        if next != 0 {
+            if let Err(err) = tiff.read_ifd(next, Ifd::Thumbnail, &mut entries, &mut visited) {
+                warn!("Skipping unreadable EXIF thumbnail directory: {}", err);
+            }
+        }
+
+        // Follow the pointers to the other directories
+        let mut i = 0;
+        while i < entries.len() {
+            let sub_ifd = match entries[i].tag {
+                EXIF_IFD_POINTER => Some(Ifd::Exif),
+                GPS_IFD_POINTER => Some(Ifd::Gps),
+                INTEROPERABILITY_IFD_POINTER => Some(Ifd::Interoperability),
+                _ => None,
+            };
+            if let (Some(ifd), Some(offset)) = (sub_ifd, entries[i].value.as_u32()) {
+                if let Err(err) = tiff.read_ifd(offset as usize, ifd, &mut entries, &mut visited) {
+                    warn!("Skipping unreadable EXIF directory {:?}: {}", ifd, err);
+                }
+            }
+            i += 1;
+        }
+
+        Ok(Exif {
+            entries: entries,
+        })
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The value of `tag` in directory `ifd`
+    pub fn get(&self, ifd: Ifd, tag: u16) -> Option<&Value> {
+        self.entries.iter()
+            .find(|entry| entry.ifd == ifd && entry.tag == tag)
+            .map(|entry| &entry.value)
+    }
+
+    pub fn orientation(&self) -> Option<Orientation> {
+        self.get(Ifd::Primary, ORIENTATION)
+            .and_then(Value::as_u32)
+            .and_then(|value| Orientation::from_value(value as u16))
+    }
+
+    /// The manufacturer of the camera
+    pub fn make(&self) -> Option<&str> {
+        self.get(Ifd::Primary, MAKE).and_then(Value::as_str)
+    }
+
+    /// The camera model
+    pub fn model(&self) -> Option<&str> {
+        self.get(Ifd::Primary, MODEL).and_then(Value::as_str)
+    }
+
+    /// When the photo was taken, as `YYYY:MM:DD HH:MM:SS`
+    pub fn date_time_original(&self) -> Option<&str> {
+        self.get(Ifd::Exif, DATE_TIME_ORIGINAL).and_then(Value::as_str)
+    }
+
+    pub fn gps_position(&self) -> Option<GpsPosition> {
+        let latitude = self.gps_coordinate(GPS_LATITUDE, GPS_LATITUDE_REF, "S")?;
+        let longitude = self.gps_coordinate(GPS_LONGITUDE, GPS_LONGITUDE_REF, "W")?;
+        let altitude = self.get(Ifd::Gps, GPS_ALTITUDE)
+            .and_then(Value::as_f64s)
+            .and_then(|values| values.first().cloned())
+            .map(|altitude| {
+                // A reference of 1 means below sea level
+                match self.get(Ifd::Gps, GPS_ALTITUDE_REF).and_then(Value::as_u32) {
+                    Some(1) => -altitude,
+                    _ => altitude,
+                }
+            });
+
+        Some(GpsPosition {
+            latitude: latitude,
+            longitude: longitude,
+            altitude: altitude,
+        })
+    }
+
+    /// Degrees from degrees, minutes and seconds
+    fn gps_coordinate(&self, tag: u16, reference: u16, negative: &str) -> Option<f64> {
+        let values = self.get(Ifd::Gps, tag)?.as_f64s()?;
+        if values.len() < 3 {
+            return None;
+        }
+
+        let degrees = values[0] + values[1] / 60.0 + values[2] / 3600.0;
+        let negate = self.get(Ifd::Gps, reference).and_then(Value::as_str) == Some(negative);
+        Some(if negate { -degrees } else { degrees })
+    }
+}
+
+pub const MAKE: u16 = 0x010F;
+pub const MODEL: u16 = 0x0110;
+pub const ORIENTATION: u16 = 0x0112;
+pub const DATE_TIME: u16 = 0x0132;
+pub const EXIF_IFD_POINTER: u16 = 0x8769;
+pub const GPS_IFD_POINTER: u16 = 0x8825;
+pub const DATE_TIME_ORIGINAL: u16 = 0x9003;
+pub const INTEROPERABILITY_IFD_POINTER: u16 = 0xA005;
+pub const GPS_LATITUDE_REF: u16 = 0x0001;
+pub const GPS_LATITUDE: u16 = 0x0002;
+pub const GPS_LONGITUDE_REF: u16 = 0x0003;
+pub const GPS_LONGITUDE: u16 = 0x0004;
+pub const GPS_ALTITUDE_REF: u16 = 0x0005;
+pub const GPS_ALTITUDE: u16 = 0x0006;
+
+/// A TIFF structure with its byte order
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Result<Self, Error> {
+        let data = if data.starts_with(b"Exif\0\0") { &data[6..] } else { data };
+        let big_endian = match data.get(..4) {
+            Some(b"II*\0") => false,
+            Some(b"MM\0*") => true,
+            _ => return Err(Error::InvalidHeader),
+        };
+
+        Ok(Tiff {
+            data: data,
+            big_endian: big_endian,
+        })
+    }
+
+    fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8], Error> {
+        let end = offset.checked_add(len).ok_or(Error::OutOfBounds)?;
+        self.data.get(offset..end).ok_or(Error::OutOfBounds)
+    }
+
+    /// Reads the entries of the directory at `offset` and returns the offset
+    /// of the next one, 0 if there is none. Unreadable entries are skipped.
+    fn read_ifd(&self, offset: usize, ifd: Ifd, entries: &mut Vec<Entry>, visited: &mut Vec<usize>) -> Result<usize, Error> {
+        // Malicious data could let directories point to each other
+        if visited.contains(&offset) {
+            return Err(Error::Loop);
+        }
+        visited.push(offset);
+
+        let n_entries = self.u16(offset)? as usize;
+        for i in 0..n_entries {
+            let entry = offset + 2 + 12 * i;
+            match self.read_value(entry) {
+                Ok(Some(value)) => entries.push(Entry {
+                    ifd: ifd,
+                    tag: self.u16(entry)?,
+                    value: value,
+                }),
+                Ok(None) => {}
+                Err(err) => warn!("Skipping unreadable EXIF entry {} of {:?}: {}", i, ifd, err),
+            }
+        }
+
+        // The pointer to the next directory is cut off along with the last entries
+        Ok(self.u32(offset + 2 + 12 * n_entries).unwrap_or(0) as usize)
+    }
+
+    /// Reads the value of the entry at `entry`, `None` if its type is unknown
+    fn read_value(&self, entry: usize) -> Result<Option<Value>, Error> {
+        let kind = self.u16(entry + 2)?;
+        let count = self.u32(entry + 4)? as usize;
+        let size = match kind {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 => 4,
+            5 | 10 | 12 => 8,
+            _ => return Ok(None),
+        };
+
+        // Values that fit into 4 bytes are stored in place of their offset
+        let len = count.checked_mul(size).ok_or(Error::OutOfBounds)?;
+        let offset = if len <= 4 { entry + 8 } else { self.u32(entry + 8)? as usize };
+        let bytes = self.bytes(offset, len)?;
+        let at = |i: usize| offset + i * size;
+
+        Ok(Some(match kind {
+            1 => Value::Byte(bytes.to_vec()),
+            2 => {
+                let text = bytes.split(|&b| b == 0).next().unwrap_or(&[]);
+                Value::Ascii(String::from_utf8_lossy(text).into_owned())
+            }
+            3 => Value::Short((0..count).map(|i| self.u16(at(i))).collect::<Result<_, _>>()?),
+            4 => Value::Long((0..count).map(|i| self.u32(at(i))).collect::<Result<_, _>>()?),
+            5 => Value::Rational((0..count).map(|i| Ok((self.u32(at(i))?, self.u32(at(i) + 4)?))).collect::<Result<_, Error>>()?),
+            6 => Value::SByte(bytes.iter().map(|&b| b as i8).collect()),
+            7 => Value::Undefined(bytes.to_vec()),
+            8 => Value::SShort((0..count).map(|i| self.u16(at(i)).map(|v| v as i16)).collect::<Result<_, _>>()?),
+            9 => Value::SLong((0..count).map(|i| self.u32(at(i)).map(|v| v as i32)).collect::<Result<_, _>>()?),
+            10 => Value::SRational((0..count).map(|i| Ok((self.u32(at(i))? as i32, self.u32(at(i) + 4)? as i32))).collect::<Result<_, Error>>()?),
+            11 => Value::Float((0..count).map(|i| self.u32(at(i)).map(f32::from_bits)).collect::<Result<_, _>>()?),
+            _ => Value::Double((0..count).map(|i| self.u64(at(i)).map(f64::from_bits)).collect::<Result<_, _>>()?),
+        }))
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, Error> {
+        let b = self.bytes(offset, 2)?;
+        let b = [b[0], b[1]];
+        Ok(if self.big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, Error> {
+        let b = self.bytes(offset, 4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Ok(if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+
+    fn u64(&self, offset: usize) -> Result<u64, Error> {
+        let (first, second) = (self.u32(offset)? as u64, self.u32(offset + 4)? as u64);
+        Ok(if self.big_endian { first << 32 | second } else { second << 32 | first })
+    }
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        InvalidHeader {
+            description("Invalid TIFF header")
+        }
+        OutOfBounds {
+            description("EXIF data points outside of the chunk")
+        }
+        Loop {
+            description("EXIF directories point to each other")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn orientation_in_both_byte_orders() {
+        let little = b"II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\0\0\0\0";
+        assert_eq!(orientation(little).unwrap(), Some(Orientation::Rotate90));
+
+        let big = b"Exif\0\0MM\0*\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x08\0\0\0\0\0\0";
+        assert_eq!(orientation(big).unwrap(), Some(Orientation::Rotate270));
+    }
+
+    /// Appends an IFD entry in little endian
+    fn entry(data: &mut Vec<u8>, tag: u16, kind: u16, count: u32, value: u32) {
+        data.extend_from_slice(&tag.to_le_bytes());
+        data.extend_from_slice(&kind.to_le_bytes());
+        data.extend_from_slice(&count.to_le_bytes());
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn tags_of_sub_directories() {
+        let mut data = b"II*\0\x08\0\0\0".to_vec();
+        data.extend_from_slice(&[3, 0]);
+        entry(&mut data, MAKE, 2, 6, 50);
+        entry(&mut data, EXIF_IFD_POINTER, 4, 1, 56);
+        entry(&mut data, GPS_IFD_POINTER, 4, 1, 94);
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(b"Canon\0");
+
+        data.extend_from_slice(&[1, 0]);
+        entry(&mut data, DATE_TIME_ORIGINAL, 2, 20, 74);
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(b"2017:06:01 12:30:00\0");
+
+        data.extend_from_slice(&[4, 0]);
+        entry(&mut data, GPS_LATITUDE_REF, 2, 2, u32::from(b'S'));
+        entry(&mut data, GPS_LATITUDE, 5, 3, 148);
+        entry(&mut data, GPS_LONGITUDE_REF, 2, 2, u32::from(b'E'));
+        entry(&mut data, GPS_LONGITUDE, 5, 3, 172);
+        data.extend_from_slice(&[0; 4]);
+        for &value in &[10, 1, 30, 1, 0, 1, 20, 1, 15, 1, 36, 10u32] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let exif = Exif::parse(&data).unwrap();
+        assert_eq!(exif.make(), Some("Canon"));
+        assert_eq!(exif.model(), None);
+        assert_eq!(exif.date_time_original(), Some("2017:06:01 12:30:00"));
+        assert_eq!(exif.get(Ifd::Gps, GPS_LATITUDE), Some(&Value::Rational(vec![(10, 1), (30, 1), (0, 1)])));
+
+        let position = exif.gps_position().unwrap();
+        assert_eq!(position.latitude, -10.5);
+        assert!((position.longitude - 20.251).abs() < 1e-9);
+        assert_eq!(position.altitude, None);
+    }
+
+    #[test]
+    fn directories_pointing_to_themselves() {
+        let mut data = b"II*\0\x08\0\0\0".to_vec();
+        data.extend_from_slice(&[1, 0]);
+        entry(&mut data, EXIF_IFD_POINTER, 4, 1, 8);
+        data.extend_from_slice(&[0; 4]);
+
+        // The loop is skipped like any other unreadable directory
+        let exif = Exif::parse(&data).unwrap();
+        assert_eq!(exif.entries().len(), 1);
+    }
+
+    #[test]
+    fn unreadable_parts_are_skipped() {
+        let mut data = b"II*\0\x08\0\0\0".to_vec();
+        data.extend_from_slice(&[4, 0]);
+        // A make whose text lies outside of the data
+        entry(&mut data, MAKE, 2, 6, 1000);
+        entry(&mut data, ORIENTATION, 3, 1, 6);
+        entry(&mut data, GPS_IFD_POINTER, 4, 1, 2000);
+        entry(&mut data, MODEL, 2, 4, u32::from_le_bytes(*b"EOS\0"));
+        // A thumbnail directory outside of the data
+        data.extend_from_slice(&3000u32.to_le_bytes());
+
+        let exif = Exif::parse(&data).unwrap();
+        assert_eq!(exif.orientation(), Some(Orientation::Rotate90));
+        assert_eq!(exif.make(), None);
+        assert_eq!(exif.model(), Some("EOS"));
+        assert_eq!(exif.gps_position(), None);
+        assert_eq!(orientation(&data).unwrap(), Some(Orientation::Rotate90));
+
+        // Without IFD0 nothing can be read
+        data[4] = 0xFF;
+        assert!(Exif::parse(&data).is_err());
+        assert!(orientation(&data).is_err());
+    }
+
+    #[test]
+    fn orientation_ignores_the_other_directories() {
+        let mut data = b"II*\0\x08\0\0\0".to_vec();
+        data.extend_from_slice(&[2, 0]);
+        entry(&mut data, ORIENTATION, 3, 1, 3);
+        entry(&mut data, EXIF_IFD_POINTER, 4, 1, 2000);
+        // A thumbnail directory outside of the data
+        data.extend_from_slice(&3000u32.to_le_bytes());
+
+        assert_eq!(orientation(&data).unwrap(), Some(Orientation::Rotate180));
+    }
+
+    #[test]
+    fn rotations_move_the_corners() {
+        assert_eq!(Orientation::Rotate90.apply(0, 0, 3, 2), (1, 0));
+        assert_eq!(Orientation::Rotate270.apply(0, 0, 3, 2), (0, 2));
+        assert_eq!(Orientation::Transverse.apply(2, 1, 3, 2), (0, 0));
+    }
+}
@@ -0,0 +1,231 @@
+use image::{Buffer, ColorType, Image, Pixels};
+use exif::Orientation;
+
+/// The value of a single sample while decoding.
+/// Transformed planes (e.g. chroma) may contain negative values.
+pub type ColorVal = i32;
+
+/// A frame that is being decoded.
+#[derive(Debug,Clone)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub planes: Vec<Vec<ColorVal>>,
+    pub delay: Option<u16>,
+    /// The earlier frame this one is an exact copy of
+    pub seen_before: Option<usize>,
+    /// The first column of every row that is stored, the others are
+    /// copied from the previous frame
+    pub col_begin: Vec<usize>,
+    /// The column after the last one of every row that is stored
+    pub col_end: Vec<usize>,
+}
+
+impl Frame {
+    /// Creates a frame without any planes.
+    /// They are allocated once the limits have been checked.
+    pub fn new(width: usize, height: usize, delay: Option<u16>) -> Self {
+        Frame {
+            width: width,
+            height: height,
+            planes: Vec::new(),
+            delay: delay,
+            seen_before: None,
+            col_begin: vec![0; height],
+            col_end: vec![width; height],
+        }
+    }
+
+    pub fn allocate_planes(&mut self, n_planes: usize) {
+        self.planes = vec![vec![0; self.width * self.height]; n_planes];
+    }
+
+    pub fn fill_plane(&mut self, p: usize, value: ColorVal) {
+        for sample in &mut self.planes[p] {
+            *sample = value;
+        }
+    }
+
+    /// Keeps only every `scale`th row and column
+    pub fn downscale(&mut self, scale: usize) {
+        let width = (self.width - 1) / scale + 1;
+        let height = (self.height - 1) / scale + 1;
+        for plane in &mut self.planes {
+            let mut downscaled = Vec::with_capacity(width * height);
+            for r in 0..height {
+                let row = r * scale * self.width;
+                downscaled.extend((0..width).map(|c| plane[row + c * scale]));
+            }
+            *plane = downscaled;
+        }
+
+        self.width = width;
+        self.height = height;
+        self.col_begin = vec![0; height];
+        self.col_end = vec![width; height];
+    }
+
+    /// Resamples the frame to `width`x`height` with a box filter.
+    /// The colors are weighted by their alpha, so that invisible pixels
+    /// don't bleed into visible ones.
+    pub fn resample(&mut self, width: usize, height: usize) {
+        let horizontal = box_weights(self.width, width);
+        let vertical = box_weights(self.height, height);
+        let has_alpha = self.planes.len() > 3;
+
+        let resampled: Vec<Vec<f64>> = self.planes.iter().enumerate().map(|(p, plane)| {
+            let weighted: Vec<f64> = if has_alpha && p < 3 {
+                plane.iter().zip(&self.planes[3]).map(|(&v, &a)| v as f64 * a as f64).collect()
+            } else {
+                plane.iter().map(|&v| v as f64).collect()
+            };
+
+            let mut rows = Vec::with_capacity(width * self.height);
+            for row in weighted.chunks(self.width) {
+                rows.extend(horizontal.iter().map(|weights| apply(weights, |x| row[x])));
+            }
+
+            let mut resampled = Vec::with_capacity(width * height);
+            for weights in &vertical {
+                resampled.extend((0..width).map(|c| apply(weights, |y| rows[y * width + c])));
+            }
+            resampled
+        }).collect();
+
+        for (p, plane) in resampled.iter().enumerate() {
+            self.planes[p] = if has_alpha && p < 3 {
+                // Undo the weighting with the resampled alpha
+                plane.iter().zip(&resampled[3])
+                    .map(|(&v, &a)| if a > 0.0 { (v / a).round() as ColorVal } else { 0 })
+                    .collect()
+            } else {
+                plane.iter().map(|&v| v.round() as ColorVal).collect()
+            };
+        }
+
+        self.width = width;
+        self.height = height;
+        self.col_begin = vec![0; height];
+        self.col_end = vec![width; height];
+    }
+
+    /// Turns the frame as `orientation` says
+    pub fn orient(&mut self, orientation: Orientation) {
+        let (width, height) = if orientation.is_transposing() {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        };
+
+        for plane in &mut self.planes {
+            let mut oriented = vec![0; plane.len()];
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let (to_x, to_y) = orientation.apply(x, y, self.width, self.height);
+                    oriented[to_y * width + to_x] = plane[y * self.width + x];
+                }
+            }
+            *plane = oriented;
+        }
+
+        self.width = width;
+        self.height = height;
+        self.col_begin = vec![0; height];
+        self.col_end = vec![width; height];
+    }
+
+    /// Interleaves the planes into samples that fit `bit_depth`
+    pub fn into_image(self, bit_depth: u8) -> Image {
+        let color_type = ColorType::from_planes(self.planes.len())
+            .expect("FLIF images have 1, 3 or 4 planes");
+        let n_samples = self.width * self.height * self.planes.len();
+        let samples = (0..n_samples).map(|i| {
+            let (pixel, p) = (i / self.planes.len(), i % self.planes.len());
+            self.planes[p][pixel]
+        });
+
+        let pixels = if bit_depth <= 8 {
+            Pixels::U8(Buffer::new(self.width, self.height, color_type, samples.map(|v| v as u8).collect()))
+        } else {
+            Pixels::U16(Buffer::new(self.width, self.height, color_type, samples.map(|v| v as u16).collect()))
+        };
+
+        Image::new(self.width as u64, self.height as u64, self.delay, bit_depth, pixels)
+    }
+}
+
+/// The source pixels every destination pixel covers, and by how much
+fn box_weights(src: usize, dst: usize) -> Vec<Vec<(usize, f64)>> {
+    let scale = src as f64 / dst as f64;
+    (0..dst).map(|i| {
+        let (begin, end) = (i as f64 * scale, (i + 1) as f64 * scale);
+        let first = begin.floor() as usize;
+        let last = (end.ceil() as usize).min(src);
+        (first..last).map(|x| {
+            let coverage = end.min(x as f64 + 1.0) - begin.max(x as f64);
+            (x, coverage / scale)
+        }).collect()
+    }).collect()
+}
+
+fn apply<F: Fn(usize) -> f64>(weights: &[(usize, f64)], sample: F) -> f64 {
+    weights.iter().map(|&(x, weight)| sample(x) * weight).sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(width: usize, height: usize, planes: Vec<Vec<ColorVal>>) -> Frame {
+        let mut frame = Frame::new(width, height, None);
+        frame.planes = planes;
+        frame
+    }
+
+    #[test]
+    fn resample_averages_covered_pixels() {
+        let mut even = frame(4, 2, vec![vec![0, 10, 20, 30, 40, 50, 60, 70]]);
+        even.resample(2, 1);
+        assert_eq!(even.planes[0], vec![25, 45]);
+
+        let mut odd = frame(3, 1, vec![vec![0, 30, 60]]);
+        odd.resample(2, 1);
+        assert_eq!(odd.planes[0], vec![10, 50]);
+    }
+
+    #[test]
+    fn resample_ignores_invisible_colors() {
+        let color = vec![100, 0];
+        let alpha = vec![255, 0];
+        let mut frame = frame(2, 1, vec![color.clone(), color.clone(), color, alpha]);
+        frame.resample(1, 1);
+        assert_eq!(frame.planes, vec![vec![100], vec![100], vec![100], vec![128]]);
+    }
+
+    #[test]
+    fn into_image_interleaves_planes() {
+        let planes = vec![vec![1, 2], vec![3, 4], vec![5, 6]];
+        let image = frame(2, 1, planes.clone()).into_image(8);
+        let buffer = match *image.pixels() {
+            Pixels::U8(ref buffer) => buffer.clone(),
+            Pixels::U16(_) => panic!("8 bit image with 16 bit samples"),
+        };
+        assert_eq!(buffer.color_type(), ColorType::RGB);
+        assert_eq!(buffer.pixel(1, 0), &[2, 4, 6]);
+        assert_eq!(buffer.rows().collect::<Vec<_>>(), vec![&[1, 3, 5, 2, 4, 6]]);
+
+        let image = frame(2, 1, planes).into_image(12);
+        assert_eq!(image.to_u16().row(0), &[1, 3, 5, 2, 4, 6]);
+        assert_eq!(image.to_u8().row(0), &[0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn orient_rotates_clockwise() {
+        // 1 2 3
+        // 4 5 6
+        let mut rotated = frame(3, 2, vec![vec![1, 2, 3, 4, 5, 6]]);
+        rotated.orient(Orientation::Rotate90);
+        assert_eq!((rotated.width, rotated.height), (2, 3));
+        assert_eq!(rotated.planes[0], vec![4, 1, 5, 2, 6, 3]);
+    }
+}
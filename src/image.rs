@@ -1,19 +1,238 @@
+use std::slice;
 
-#[derive(Debug,Copy,Clone)]
+/// A decoded frame
+#[derive(Debug,Clone)]
 pub struct Image {
     width: u64,
     height: u64,
     delay: Option<u16>,
+    bit_depth: u8,
+    pixels: Pixels,
 }
 
 impl Image {
-    pub fn new(width: u64, height: u64, delay: Option<u16>) -> Self {
+    /// `bit_depth` is the number of bits the samples of `pixels` use
+    pub fn new(width: u64, height: u64, delay: Option<u16>, bit_depth: u8, pixels: Pixels) -> Self {
         Image {
             width: width,
             height: height,
             delay: delay,
+            bit_depth: bit_depth,
+            pixels: pixels,
         }
     }
+
+    pub fn width(&self) -> u64 {
+        self.width
+    }
+
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Delay of this frame in milliseconds (only set for animations)
+    pub fn delay(&self) -> Option<u16> {
+        self.delay
+    }
+
+    /// The number of bits per sample, up to 16
+    pub fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+
+    pub fn color_type(&self) -> ColorType {
+        self.pixels.color_type()
+    }
+
+    pub fn num_planes(&self) -> usize {
+        self.color_type().channels()
+    }
+
+    /// The interleaved samples, 8 bit wide for bit depths up to 8
+    pub fn pixels(&self) -> &Pixels {
+        &self.pixels
+    }
+
+    /// The samples as 8 bit values.
+    /// Deeper samples are reduced to their 8 most significant bits.
+    pub fn to_u8(&self) -> Buffer<u8> {
+        match self.pixels {
+            Pixels::U8(ref buffer) => buffer.clone(),
+            Pixels::U16(ref buffer) => {
+                let shift = self.bit_depth.saturating_sub(8);
+                buffer.map(|sample| (sample >> shift) as u8)
+            }
+        }
+    }
+
+    /// The samples as 16 bit values, without changing their bit depth
+    pub fn to_u16(&self) -> Buffer<u16> {
+        match self.pixels {
+            Pixels::U8(ref buffer) => buffer.map(u16::from),
+            Pixels::U16(ref buffer) => buffer.clone(),
+        }
+    }
+
+    /// The samples as 8 bit RGBA values, whatever the color type.
+    /// Gray is copied into all three colors, and missing alpha is opaque.
+    pub fn to_rgba8(&self) -> Buffer<u8> {
+        self.to_u8().to_rgba(u8::MAX)
+    }
+
+    /// Like `to_rgba8`, but with 16 bit values of the image's bit depth
+    pub fn to_rgba16(&self) -> Buffer<u16> {
+        let opaque = (1u32 << self.bit_depth) - 1;
+        self.to_u16().to_rgba(opaque as u16)
+    }
+}
+
+/// The channels of a pixel, in the order they are interleaved
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum ColorType {
+    Gray,
+    RGB,
+    RGBA,
+}
+
+impl ColorType {
+    /// The color type of an image with `n_planes` planes
+    pub fn from_planes(n_planes: usize) -> Option<Self> {
+        Some(match n_planes {
+            1 => ColorType::Gray,
+            3 => ColorType::RGB,
+            4 => ColorType::RGBA,
+            _ => return None,
+        })
+    }
+
+    pub fn channels(&self) -> usize {
+        match *self {
+            ColorType::Gray => 1,
+            ColorType::RGB => 3,
+            ColorType::RGBA => 4,
+        }
+    }
+}
+
+/// The samples of an image, with the width that fits its bit depth
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum Pixels {
+    U8(Buffer<u8>),
+    U16(Buffer<u16>),
+}
+
+impl Pixels {
+    pub fn color_type(&self) -> ColorType {
+        match *self {
+            Pixels::U8(ref buffer) => buffer.color_type(),
+            Pixels::U16(ref buffer) => buffer.color_type(),
+        }
+    }
+}
+
+/// Interleaved samples, row by row
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Buffer<T> {
+    width: usize,
+    height: usize,
+    color_type: ColorType,
+    samples: Vec<T>,
+}
+
+impl<T: Copy> Buffer<T> {
+    /// Panics if the number of samples doesn't match the dimensions
+    pub fn new(width: usize, height: usize, color_type: ColorType, samples: Vec<T>) -> Self {
+        assert_eq!(samples.len(), width * height * color_type.channels());
+
+        Buffer {
+            width: width,
+            height: height,
+            color_type: color_type,
+            samples: samples,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn color_type(&self) -> ColorType {
+        self.color_type
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.samples
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        self.samples
+    }
+
+    /// The samples of row `y`
+    pub fn row(&self, y: usize) -> &[T] {
+        let len = self.row_len();
+        &self.samples[y * len..(y + 1) * len]
+    }
+
+    /// The channels of the pixel in column `x` of row `y`
+    pub fn pixel(&self, x: usize, y: usize) -> &[T] {
+        let channels = self.color_type.channels();
+        &self.row(y)[x * channels..(x + 1) * channels]
+    }
+
+    pub fn rows<'a>(&'a self) -> Rows<'a, T> {
+        Rows {
+            chunks: self.samples.chunks(self.row_len()),
+        }
+    }
+
+    fn row_len(&self) -> usize {
+        self.width * self.color_type.channels()
+    }
+
+    fn to_rgba(&self, opaque: T) -> Buffer<T> {
+        if self.color_type == ColorType::RGBA {
+            return self.clone();
+        }
+
+        let channels = self.color_type.channels();
+        let mut samples = Vec::with_capacity(self.width * self.height * 4);
+        for pixel in self.samples.chunks(channels) {
+            match self.color_type {
+                ColorType::Gray => samples.extend_from_slice(&[pixel[0], pixel[0], pixel[0]]),
+                _ => samples.extend_from_slice(&pixel[..3]),
+            }
+            samples.push(opaque);
+        }
+
+        Buffer::new(self.width, self.height, ColorType::RGBA, samples)
+    }
+
+    fn map<U, F: Fn(T) -> U>(&self, f: F) -> Buffer<U> {
+        Buffer {
+            width: self.width,
+            height: self.height,
+            color_type: self.color_type,
+            samples: self.samples.iter().map(|&sample| f(sample)).collect(),
+        }
+    }
+}
+
+/// Iterates over the rows of a `Buffer`
+pub struct Rows<'a, T: 'a> {
+    chunks: slice::Chunks<'a, T>,
+}
+
+impl<'a, T> Iterator for Rows<'a, T> {
+    type Item = &'a [T];
+
+    fn next(&mut self) -> Option<&'a [T]> {
+        self.chunks.next()
+    }
 }
 
 #[derive(Debug,Copy,Clone,PartialEq,Eq)]
@@ -21,3 +240,35 @@ pub enum Movement {
     Static,
     Animated,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gray_becomes_opaque_rgba() {
+        let buffer = Buffer::new(2, 1, ColorType::Gray, vec![7, 200]);
+        let image = Image::new(2, 1, None, 8, Pixels::U8(buffer));
+
+        let rgba = image.to_rgba8();
+        assert_eq!(rgba.color_type(), ColorType::RGBA);
+        assert_eq!(rgba.as_slice(), &[7, 7, 7, 255, 200, 200, 200, 255]);
+    }
+
+    #[test]
+    fn rgb_gets_alpha_of_its_bit_depth() {
+        let buffer = Buffer::new(1, 2, ColorType::RGB, vec![1, 2, 3, 1023, 0, 512]);
+        let image = Image::new(1, 2, None, 10, Pixels::U16(buffer));
+
+        assert_eq!(image.to_rgba16().as_slice(), &[1, 2, 3, 1023, 1023, 0, 512, 1023]);
+        assert_eq!(image.to_rgba8().as_slice(), &[0, 0, 0, 255, 255, 0, 128, 255]);
+    }
+
+    #[test]
+    fn rgba_is_unchanged() {
+        let buffer = Buffer::new(1, 1, ColorType::RGBA, vec![1, 2, 3, 4]);
+        let image = Image::new(1, 1, None, 8, Pixels::U8(buffer.clone()));
+
+        assert_eq!(image.to_rgba8(), buffer);
+    }
+}
@@ -1,3 +1,5 @@
+#![allow(clippy::redundant_field_names, clippy::manual_div_ceil, clippy::manual_is_multiple_of)]
+
 #[macro_use]
 extern crate quick_error;
 extern crate podio;
@@ -6,10 +8,15 @@ extern crate flate2;
 extern crate log;
 
 mod image;
+mod frame;
 pub mod dec;
 mod varint;
 mod format;
-mod metadata;
+pub mod metadata;
+pub mod exif;
 mod maniac;
+mod colors;
+mod transform;
+mod pixels;
 
 pub use image::*;
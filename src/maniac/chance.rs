@@ -0,0 +1,166 @@
+/// Number of bits a symbol may have at most.
+/// 18 bits are enough for the differences of 16 bit YCoCg values.
+pub const SYMBOL_BITS: usize = 18;
+
+const ZERO_CHANCE: u16 = 1000;
+const SIGN_CHANCE: u16 = 2048;
+const EXP_CHANCES: [u16; SYMBOL_BITS - 1] = [
+    1000, 1200, 1500, 1750, 2000, 2300, 2800, 2400, 2300,
+    2048, 2048, 2048, 2048, 2048, 2048, 2048, 2048,
+];
+const MANT_CHANCES: [u16; SYMBOL_BITS] = [
+    1900, 1850, 1800, 1750, 1650, 1600, 1600, 2048, 2048,
+    2048, 2048, 2048, 2048, 2048, 2048, 2048, 2048, 2048,
+];
+
+/// Lookup table to adapt a 12 bit chance after a bit has been read.
+#[derive(Clone)]
+pub struct ChanceTable {
+    next: [Vec<u16>; 2],
+}
+
+impl ChanceTable {
+    /// Builds the update table.
+    /// `alpha` is the adaption speed (as fraction of `u32::MAX`) and
+    /// `cutoff` keeps chances away from 0 and 4096.
+    pub fn new(cutoff: u8, alpha: u32) -> Self {
+        const SIZE: u64 = 4096;
+        const ONE: u64 = 1 << 32;
+        let max_p = SIZE - cutoff as u64;
+        let factor = alpha as u64;
+
+        let mut zero_state = vec![0; SIZE as usize];
+        let mut one_state = vec![0; SIZE as usize];
+
+        let mut last_p8 = 0;
+        let mut p = ONE / 2;
+        for _ in 0..SIZE / 2 {
+            let mut p8 = (SIZE * p + ONE / 2) >> 32;
+            if p8 <= last_p8 {
+                p8 = last_p8 + 1;
+            }
+            if last_p8 != 0 && last_p8 < SIZE && p8 <= max_p {
+                one_state[last_p8 as usize] = p8 as u16;
+            }
+
+            p += ((ONE - p) * factor + ONE / 2) >> 32;
+            last_p8 = p8;
+        }
+
+        for i in (SIZE - max_p)..(max_p + 1) {
+            if one_state[i as usize] != 0 {
+                continue;
+            }
+
+            let mut p = (i * ONE + SIZE / 2) / SIZE;
+            p += ((ONE - p) * factor + ONE / 2) >> 32;
+            let mut p8 = (SIZE * p + ONE / 2) >> 32;
+            if p8 <= i {
+                p8 = i + 1;
+            }
+            if p8 > max_p {
+                p8 = max_p;
+            }
+            one_state[i as usize] = p8 as u16;
+        }
+
+        for i in 1..SIZE as usize {
+            zero_state[i] = SIZE as u16 - one_state[SIZE as usize - i];
+        }
+
+        ChanceTable {
+            next: [zero_state, one_state],
+        }
+    }
+
+    /// The chance that follows `chance` after reading `bit`
+    pub fn next(&self, bit: bool, chance: u16) -> u16 {
+        self.next[bit as usize][chance as usize]
+    }
+}
+
+impl Default for ChanceTable {
+    fn default() -> Self {
+        ChanceTable::new(2, u32::MAX / 19)
+    }
+}
+
+/// The bits a near-zero symbol is made of
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum SymbolBit {
+    Zero,
+    Sign,
+    /// Exponent bit `i` of a positive (`true`) or negative (`false`) number
+    Exp(usize, bool),
+    Mant(usize),
+}
+
+/// The adaptive chances of a single near-zero symbol context
+#[derive(Debug,Clone)]
+pub struct SymbolChances {
+    zero: u16,
+    sign: u16,
+    exp: [u16; 2 * (SYMBOL_BITS - 1)],
+    mant: [u16; SYMBOL_BITS],
+}
+
+impl SymbolChances {
+    pub fn new() -> Self {
+        let mut exp = [0; 2 * (SYMBOL_BITS - 1)];
+        for (i, &chance) in EXP_CHANCES.iter().enumerate() {
+            exp[2 * i] = chance;
+            exp[2 * i + 1] = chance;
+        }
+
+        SymbolChances {
+            zero: ZERO_CHANCE,
+            sign: SIGN_CHANCE,
+            exp: exp,
+            mant: MANT_CHANCES,
+        }
+    }
+
+    pub fn get_mut(&mut self, bit: SymbolBit) -> &mut u16 {
+        match bit {
+            SymbolBit::Zero => &mut self.zero,
+            SymbolBit::Sign => &mut self.sign,
+            SymbolBit::Exp(i, sign) => &mut self.exp[2 * i + sign as usize],
+            SymbolBit::Mant(i) => &mut self.mant[i],
+        }
+    }
+}
+
+impl Default for SymbolChances {
+    fn default() -> Self {
+        SymbolChances::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn table_stays_within_cutoff() {
+        let cutoff = 4;
+        let table = ChanceTable::new(cutoff, u32::MAX / 19);
+        let mut chance = 2048;
+        for _ in 0..1000 {
+            chance = table.next(true, chance);
+        }
+        assert_eq!(chance, 4096 - cutoff as u16);
+        for _ in 0..1000 {
+            chance = table.next(false, chance);
+        }
+        assert_eq!(chance, cutoff as u16);
+    }
+
+    #[test]
+    fn table_adapts_towards_bit() {
+        let table = ChanceTable::default();
+        for chance in 100..4000 {
+            assert!(table.next(true, chance) > chance);
+            assert!(table.next(false, chance) < chance);
+        }
+    }
+}
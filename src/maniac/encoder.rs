@@ -0,0 +1,237 @@
+//! The counterpart of the decoders, to build input for the tests
+
+use super::chance::{ChanceTable, SymbolBit, SymbolChances};
+use super::rac::{Config, Config24};
+use super::tree::{PropertyDecoder, Tree};
+
+/// Range encoder that matches `rac::Input24`
+pub struct Output {
+    data: Vec<u8>,
+    range: u32,
+    low: u32,
+    /// The last byte, which may still get a carry
+    delayed_byte: Option<u8>,
+    /// The 0xFF bytes after the delayed one, which a carry would turn into 0x00
+    delayed_count: usize,
+}
+
+impl Output {
+    pub fn new() -> Self {
+        Output {
+            data: Vec::new(),
+            range: Config24::base_range(),
+            low: 0,
+            delayed_byte: None,
+            delayed_count: 0,
+        }
+    }
+
+    fn put(&mut self, chance: u32, bit: bool) {
+        if bit {
+            self.low += self.range - chance;
+            self.range = chance;
+        } else {
+            self.range -= chance;
+        }
+        self.output();
+    }
+
+    fn output(&mut self) {
+        let min_range = Config24::min_range();
+        while self.range <= min_range {
+            let byte = self.low >> Config24::min_range_bits();
+            match self.delayed_byte {
+                None => self.delayed_byte = Some(byte as u8),
+                Some(delayed) if (self.low + self.range) >> 8 < min_range => {
+                    // No carry can reach the delayed bytes anymore
+                    self.data.push(delayed);
+                    self.data.extend((0..self.delayed_count).map(|_| 0xFF));
+                    self.delayed_count = 0;
+                    self.delayed_byte = Some(byte as u8);
+                }
+                Some(delayed) if self.low >> 8 >= min_range => {
+                    self.data.push(delayed + 1);
+                    self.data.extend((0..self.delayed_count).map(|_| 0x00));
+                    self.delayed_count = 0;
+                    self.delayed_byte = Some(byte as u8);
+                }
+                Some(_) => self.delayed_count += 1,
+            }
+            self.low = (self.low & (min_range - 1)) << 8;
+            self.range <<= 8;
+        }
+    }
+
+    pub fn write_bit(&mut self, bit: bool) {
+        let chance = self.range >> 1;
+        self.put(chance, bit);
+    }
+
+    pub fn write_12bit_chance(&mut self, b12: u16, bit: bool) {
+        let chance = Config24::chance_12bit_chance(b12 as isize, self.range);
+        self.put(chance, bit);
+    }
+
+    /// Like `UniformSymbolDecoder::read_int`
+    pub fn write_int(&mut self, min: isize, max: isize, value: isize) {
+        assert!(min <= value && value <= max);
+        if min == max {
+            return;
+        }
+
+        let med = min + (max - min) / 2;
+        if value > med {
+            self.write_bit(true);
+            self.write_int(med + 1, max, value);
+        } else {
+            self.write_bit(false);
+            self.write_int(min, med, value);
+        }
+    }
+
+    /// The encoded data, with enough bytes to decode all of it
+    pub fn finish(mut self) -> Vec<u8> {
+        self.low += Config24::min_range() - 1;
+        for _ in 0..4 {
+            self.range = Config24::min_range() - 1;
+            self.output();
+        }
+        self.data
+    }
+}
+
+/// Like `SimpleSymbolDecoder`
+pub struct SimpleSymbolEncoder {
+    chances: SymbolChances,
+    table: ChanceTable,
+}
+
+impl SimpleSymbolEncoder {
+    pub fn new() -> Self {
+        SimpleSymbolEncoder {
+            chances: SymbolChances::new(),
+            table: ChanceTable::default(),
+        }
+    }
+
+    pub fn write_int(&mut self, output: &mut Output, min: i32, max: i32, value: i32) {
+        write_near_zero(output, &mut self.chances, &self.table, min, max, value);
+    }
+
+    pub fn write_bool(&mut self, output: &mut Output, value: bool) {
+        self.write_int(output, 0, 1, value as i32);
+    }
+}
+
+/// Like `PropertyDecoder`, with a tree that has been read back already
+pub struct PropertyEncoder {
+    decoder: PropertyDecoder,
+}
+
+impl PropertyEncoder {
+    pub fn new(tree: Tree) -> Self {
+        PropertyEncoder {
+            decoder: PropertyDecoder::new(tree, ChanceTable::default()),
+        }
+    }
+
+    pub fn write_int(&mut self, output: &mut Output, properties: &[i32], min: i32, max: i32, value: i32) {
+        if min == max {
+            return;
+        }
+
+        let (chances, table) = self.decoder.chances(properties);
+        write_near_zero(output, chances, table, min, max, value);
+    }
+}
+
+/// Like `read_near_zero`
+pub fn write_near_zero(output: &mut Output, chances: &mut SymbolChances, table: &ChanceTable, min: i32, max: i32, value: i32) {
+    assert!(min <= value && value <= max);
+    if min > 0 {
+        return write_near_zero(output, chances, table, 0, max - min, value - min);
+    }
+    if max < 0 {
+        return write_near_zero(output, chances, table, min - max, 0, value - max);
+    }
+    if min == max {
+        return;
+    }
+
+    write_bit(output, chances, table, SymbolBit::Zero, value == 0);
+    if value == 0 {
+        return;
+    }
+
+    let sign = value > 0;
+    if min < 0 && max > 0 {
+        write_bit(output, chances, table, SymbolBit::Sign, sign);
+    }
+
+    let amax = if sign { max } else { -min };
+    let emax = ilog2(amax);
+    let a = value.abs();
+    let e = ilog2(a);
+    for i in 0..emax {
+        // The exponent is unary, and its last bit is left out at the maximum
+        write_bit(output, chances, table, SymbolBit::Exp(i, sign), i == e);
+        if i == e {
+            break;
+        }
+    }
+
+    let mut have = 1 << e;
+    for pos in (0..e).rev() {
+        let minabs1 = have | (1 << pos);
+        if minabs1 > amax {
+            continue;
+        }
+        let bit = a & (1 << pos) != 0;
+        write_bit(output, chances, table, SymbolBit::Mant(pos), bit);
+        if bit {
+            have = minabs1;
+        }
+    }
+}
+
+fn write_bit(output: &mut Output, chances: &mut SymbolChances, table: &ChanceTable, bit: SymbolBit, value: bool) {
+    let chance = chances.get_mut(bit);
+    output.write_12bit_chance(*chance, value);
+    *chance = table.next(value, *chance);
+}
+
+fn ilog2(value: i32) -> usize {
+    31 - value.leading_zeros() as usize
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maniac::rac::Input24;
+    use maniac::UniformSymbolDecoder;
+    use maniac::symbol::SimpleSymbolDecoder;
+
+    #[test]
+    fn decodes_what_was_encoded() {
+        let values: Vec<(i32, i32, i32)> = (0..2000).map(|i| {
+            let (min, max) = (-(i % 300), i % 257);
+            (min, max, i * 7919 % (max - min + 1) + min)
+        }).collect();
+
+        let mut output = Output::new();
+        let mut coder = SimpleSymbolEncoder::new();
+        for &(min, max, value) in &values {
+            coder.write_int(&mut output, min, max, value);
+            output.write_int(min as isize, max as isize, value as isize);
+        }
+        let data = output.finish();
+
+        let mut decoder = UniformSymbolDecoder::new(Input24::new(&data[..]).unwrap());
+        let mut coder = SimpleSymbolDecoder::default();
+        for &(min, max, value) in &values {
+            assert_eq!(coder.read_int(decoder.rac(), min, max).unwrap(), value);
+            assert_eq!(decoder.read_int(min as isize, max as isize).unwrap(), value as isize);
+        }
+        assert_eq!(decoder.rac().garbage_bytes(), 0);
+    }
+}
@@ -1,5 +1,8 @@
 pub mod rac;
 pub mod symbol;
+pub mod chance;
+pub mod tree;
+#[cfg(test)]
+pub mod encoder;
 
 pub use self::symbol::UniformSymbolDecoder;
-pub use self::rac::Config24;
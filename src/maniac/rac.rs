@@ -39,7 +39,7 @@ impl Config for Config24 {
             (range * b12 + 0x800) >> 12
         }
         else {
-            ((((range & 0xFFF) * b12 + 0x800) >> 12) + ((range >> 12) * b12))
+            (((range & 0xFFF) * b12 + 0x800) >> 12) + ((range >> 12) * b12)
         }
     }
 }
@@ -50,7 +50,8 @@ pub struct Input<C: Config, R> {
     r: R,
     range: C::Data,
     low: C::Data,
-
+    /// How many bytes were made up after the end of the input
+    garbage: usize,
 }
 
 impl<C: Config, R: Read> Input<C, R> {
@@ -60,6 +61,7 @@ impl<C: Config, R: Read> Input<C, R> {
             r: r,
             range: C::base_range(),
             low: 0.into(),
+            garbage: 0,
         };
 
         let mut range = C::base_range();
@@ -67,7 +69,8 @@ impl<C: Config, R: Read> Input<C, R> {
         trace!("=== RacInput init ===");
         while range > 1.into() {
             this.low <<= 8;
-            this.low |= this.read_catch_eof()?;
+            let byte = this.read_catch_eof()?;
+            this.low |= byte;
             trace!("low = {:X}", this.low);
             range >>= 8;
         }
@@ -75,20 +78,31 @@ impl<C: Config, R: Read> Input<C, R> {
         Ok(this)
     }
 
+    /// Reads the next byte, like the reference decoder substitutes
+    /// garbage for the bytes of truncated files
     pub fn read_catch_eof(&mut self) -> Result<C::Data, Error> {
         let data = &mut [0];
         Ok(match self.r.read(data)? {
-            0 => 0xFF, // Garbage
+            0 => {
+                self.garbage += 1;
+                0xFF
+            }
             _ => data[0],
         }.into())
     }
 
+    /// How many bytes after the end of the input have been read
+    pub fn garbage_bytes(&self) -> usize {
+        self.garbage
+    }
+
     pub fn input(&mut self) -> Result<(), Error> {
         for _ in 0..2 {
             if self.range <= C::min_range() {
                 self.low <<= 8;
                 self.range <<= 8;
-                self.low |= self.read_catch_eof()?;
+                let byte = self.read_catch_eof()?;
+                self.low |= byte;
             }
         }
         Ok(())
@@ -96,7 +110,7 @@ impl<C: Config, R: Read> Input<C, R> {
 
     pub fn get(&mut self, chance: C::Data) -> Result<bool, Error> {
         assert!(chance > 0.into());
-        assert!(chance < self.range.into());
+        assert!(chance < self.range);
 
         if self.low >= self.range - chance {
             self.low -= self.range - chance;
@@ -136,3 +150,18 @@ quick_error! {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_garbage_after_the_end() {
+        let mut rac = Input24::new(&[0x12, 0x34][..]).unwrap();
+        assert_eq!(rac.garbage_bytes(), 1);
+        assert_eq!(rac.low, 0x1234FF);
+
+        rac.read_catch_eof().unwrap();
+        assert_eq!(rac.garbage_bytes(), 2);
+    }
+}
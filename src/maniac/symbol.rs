@@ -1,5 +1,6 @@
 use std::io::Read;
 use super::rac;
+use super::chance::{ChanceTable, SymbolChances, SymbolBit};
 
 pub struct UniformSymbolDecoder<C: rac::Config, R> {
     rac: rac::Input<C, R>
@@ -12,6 +13,11 @@ impl<C: rac::Config, R: Read> UniformSymbolDecoder<C, R> {
         }
     }
 
+    /// The underlying range decoder, for use with the adaptive decoders
+    pub fn rac(&mut self) -> &mut rac::Input<C, R> {
+        &mut self.rac
+    }
+
     pub fn read_int(&mut self, min: isize, mut max: isize) -> Result<isize, Error> {
         assert!(max >= min);
         if min != 0 {
@@ -37,11 +43,107 @@ impl<C: rac::Config, R: Read> UniformSymbolDecoder<C, R> {
         Ok(self.read_int(0, 1)? == 1)
     }
 
+    #[allow(dead_code)]
     pub fn read_int_bits(&mut self, bits: isize) -> Result<isize, Error> {
         self.read_int(0, (1<<bits)-1)
     }
 }
 
+/// Decodes integers with a single adaptive context.
+pub struct SimpleSymbolDecoder {
+    chances: SymbolChances,
+    table: ChanceTable,
+}
+
+impl SimpleSymbolDecoder {
+    pub fn new(table: ChanceTable) -> Self {
+        SimpleSymbolDecoder {
+            chances: SymbolChances::new(),
+            table: table,
+        }
+    }
+
+    pub fn read_int<C: rac::Config, R: Read>(&mut self, rac: &mut rac::Input<C, R>, min: i32, max: i32) -> Result<i32, Error> {
+        read_near_zero(rac, &mut self.chances, &self.table, min, max)
+    }
+
+    pub fn read_bool<C: rac::Config, R: Read>(&mut self, rac: &mut rac::Input<C, R>) -> Result<bool, Error> {
+        Ok(self.read_int(rac, 0, 1)? == 1)
+    }
+}
+
+impl Default for SimpleSymbolDecoder {
+    fn default() -> Self {
+        SimpleSymbolDecoder::new(ChanceTable::default())
+    }
+}
+
+/// Reads an integer in the range `min..=max` using the near-zero encoding.
+///
+/// The number is split into a zero flag, a sign, a unary exponent and
+/// the mantissa bits, each of them with their own adaptive chance.
+/// Small absolute values are therefore cheap.
+pub fn read_near_zero<C: rac::Config, R: Read>(rac: &mut rac::Input<C, R>, chances: &mut SymbolChances, table: &ChanceTable, min: i32, max: i32) -> Result<i32, Error> {
+    assert!(max >= min);
+    if min > 0 {
+        return Ok(read_near_zero(rac, chances, table, 0, max - min)? + min);
+    }
+    if max < 0 {
+        return Ok(read_near_zero(rac, chances, table, min - max, 0)? + max);
+    }
+    if min == max {
+        return Ok(min);
+    }
+
+    if read_bit(rac, chances, table, SymbolBit::Zero)? {
+        return Ok(0);
+    }
+
+    let sign = if min == 0 {
+        true
+    } else if max == 0 {
+        false
+    } else {
+        read_bit(rac, chances, table, SymbolBit::Sign)?
+    };
+
+    let amax = if sign { max } else { -min };
+    let emax = ilog2(amax);
+
+    let mut e = 0;
+    while e < emax {
+        if read_bit(rac, chances, table, SymbolBit::Exp(e, sign))? {
+            break;
+        }
+        e += 1;
+    }
+
+    let mut have = 1 << e;
+    for pos in (0..e).rev() {
+        let minabs1 = have | (1 << pos);
+        // Skip the bit if setting it would exceed the range
+        if minabs1 > amax {
+            continue;
+        }
+        if read_bit(rac, chances, table, SymbolBit::Mant(pos))? {
+            have = minabs1;
+        }
+    }
+
+    Ok(if sign { have } else { -have })
+}
+
+fn read_bit<C: rac::Config, R: Read>(rac: &mut rac::Input<C, R>, chances: &mut SymbolChances, table: &ChanceTable, bit: SymbolBit) -> Result<bool, Error> {
+    let chance = chances.get_mut(bit);
+    let value = rac.read_12bit_chance(*chance)?;
+    *chance = table.next(value, *chance);
+    Ok(value)
+}
+
+fn ilog2(value: i32) -> usize {
+    31 - value.leading_zeros() as usize
+}
+
 quick_error! {
     #[derive(Debug)]
     pub enum Error {
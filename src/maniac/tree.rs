@@ -0,0 +1,230 @@
+use std::io::Read;
+use super::rac;
+use super::chance::{ChanceTable, SymbolChances};
+use super::symbol::{self, SimpleSymbolDecoder};
+
+const CONTEXT_TREE_MIN_COUNT: i32 = 1;
+const CONTEXT_TREE_MAX_COUNT: i32 = 512;
+
+#[derive(Debug,Clone)]
+struct Node {
+    /// The property to test, `None` for leaves
+    property: Option<usize>,
+    /// How many more times this node acts as leaf before it splits
+    count: i32,
+    /// Values greater than `splitval` go to `child`, the others to `child + 1`
+    splitval: i32,
+    child: usize,
+    /// The chances used while this node acts as leaf
+    leaf: usize,
+}
+
+impl Node {
+    fn leaf() -> Self {
+        Node {
+            property: None,
+            count: 0,
+            splitval: 0,
+            child: 0,
+            leaf: 0,
+        }
+    }
+}
+
+/// A MANIAC decision tree, which maps the properties of a pixel to a context.
+#[derive(Debug,Clone)]
+pub struct Tree {
+    nodes: Vec<Node>,
+}
+
+impl Tree {
+    /// A tree that consists of a single leaf
+    pub fn new() -> Self {
+        Tree {
+            nodes: vec![Node::leaf()],
+        }
+    }
+
+    /// Reads a tree in pre-order.
+    /// `ranges` are the `(min, max)` values every property can take.
+    pub fn read<C: rac::Config, R: Read>(rac: &mut rac::Input<C, R>, ranges: &[(i32, i32)]) -> Result<Self, Error> {
+        let mut property_decoder = SimpleSymbolDecoder::default();
+        let mut count_decoder = SimpleSymbolDecoder::default();
+        let mut split_decoder = SimpleSymbolDecoder::default();
+        let n_properties = ranges.len() as i32;
+
+        let mut nodes = vec![Node::leaf()];
+        // Recursing could overflow the stack on malicious input, so keep
+        // the subtrees that still need to be read (and their ranges) here.
+        let mut pending = vec![(0, ranges.to_vec())];
+
+        while let Some((pos, mut subrange)) = pending.pop() {
+            let property = property_decoder.read_int(rac, 0, n_properties)? - 1;
+            if property < 0 {
+                continue;
+            }
+            let property = property as usize;
+
+            let (min, max) = subrange[property];
+            if min >= max {
+                return Err(Error::InvalidTree);
+            }
+
+            let count = count_decoder.read_int(rac, CONTEXT_TREE_MIN_COUNT, CONTEXT_TREE_MAX_COUNT)?;
+            let splitval = split_decoder.read_int(rac, min, max - 1)?;
+            let child = nodes.len();
+            nodes.push(Node::leaf());
+            nodes.push(Node::leaf());
+            nodes[pos] = Node {
+                property: Some(property),
+                count: count,
+                splitval: splitval,
+                child: child,
+                leaf: 0,
+            };
+
+            // The subtree for values > splitval comes first,
+            // so it has to end up on top of the stack.
+            let mut lower = subrange.clone();
+            lower[property] = (min, splitval);
+            subrange[property] = (splitval + 1, max);
+            pending.push((child + 1, lower));
+            pending.push((child, subrange));
+        }
+
+        trace!("read tree with {} nodes", nodes.len());
+
+        Ok(Tree {
+            nodes: nodes,
+        })
+    }
+}
+
+impl Default for Tree {
+    fn default() -> Self {
+        Tree::new()
+    }
+}
+
+/// Decodes near-zero integers with the context chosen by a MANIAC tree.
+///
+/// Inner nodes act as leaves until their counter runs out. Then they split
+/// and both children continue with a copy of the chances learned so far.
+pub struct PropertyDecoder {
+    tree: Tree,
+    leaves: Vec<SymbolChances>,
+    table: ChanceTable,
+}
+
+impl PropertyDecoder {
+    pub fn new(mut tree: Tree, table: ChanceTable) -> Self {
+        tree.nodes[0].leaf = 0;
+
+        PropertyDecoder {
+            tree: tree,
+            leaves: vec![SymbolChances::new()],
+            table: table,
+        }
+    }
+
+    pub fn read_int<C: rac::Config, R: Read>(&mut self, rac: &mut rac::Input<C, R>, properties: &[i32], min: i32, max: i32) -> Result<i32, Error> {
+        if min == max {
+            return Ok(min);
+        }
+
+        let leaf = self.find_leaf(properties);
+        Ok(symbol::read_near_zero(rac, &mut self.leaves[leaf], &self.table, min, max)?)
+    }
+
+    fn find_leaf(&mut self, properties: &[i32]) -> usize {
+        let mut pos = 0;
+        loop {
+            let node = &mut self.tree.nodes[pos];
+            let property = match node.property {
+                Some(property) => property,
+                None => return node.leaf,
+            };
+
+            if node.count > 0 {
+                node.count -= 1;
+                return node.leaf;
+            }
+
+            let greater = properties[property] > node.splitval;
+            if node.count < 0 {
+                pos = if greater { node.child } else { node.child + 1 };
+                continue;
+            }
+
+            // The counter just ran out, split this node
+            node.count -= 1;
+            let child = node.child;
+            let old_leaf = node.leaf;
+            let new_leaf = self.leaves.len();
+            let chances = self.leaves[old_leaf].clone();
+            self.leaves.push(chances);
+            self.tree.nodes[child].leaf = old_leaf;
+            self.tree.nodes[child + 1].leaf = new_leaf;
+
+            return if greater { old_leaf } else { new_leaf };
+        }
+    }
+}
+
+#[cfg(test)]
+impl PropertyDecoder {
+    /// The chances `read_int` would use for these properties, for the test encoder
+    pub fn chances(&mut self, properties: &[i32]) -> (&mut SymbolChances, &ChanceTable) {
+        let leaf = self.find_leaf(properties);
+        (&mut self.leaves[leaf], &self.table)
+    }
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        InvalidTree {
+            description("Invalid MANIAC tree")
+        }
+        Symbol(err: symbol::Error) {
+            from()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn split_tree(count: i32, splitval: i32) -> Tree {
+        Tree {
+            nodes: vec![
+                Node {
+                    property: Some(1),
+                    count: count,
+                    splitval: splitval,
+                    child: 1,
+                    leaf: 0,
+                },
+                Node::leaf(),
+                Node::leaf(),
+            ],
+        }
+    }
+
+    #[test]
+    fn node_acts_as_leaf_until_count_runs_out() {
+        let mut decoder = PropertyDecoder::new(split_tree(2, 10), ChanceTable::default());
+        assert_eq!(decoder.find_leaf(&[0, 20]), 0);
+        assert_eq!(decoder.find_leaf(&[0, 5]), 0);
+        assert_eq!(decoder.leaves.len(), 1);
+
+        // Splits now, the lower branch gets a copy of the chances
+        assert_eq!(decoder.find_leaf(&[0, 5]), 1);
+        assert_eq!(decoder.leaves.len(), 2);
+
+        assert_eq!(decoder.find_leaf(&[0, 11]), 0);
+        assert_eq!(decoder.find_leaf(&[0, 10]), 1);
+        assert_eq!(decoder.leaves.len(), 2);
+    }
+}
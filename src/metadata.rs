@@ -1,66 +1,178 @@
+use std::cmp;
 use std::io::{self, Read};
 use std::fmt::{self, Debug};
 use podio::ReadPodExt;
 use varint::{self, ReadVarintExt};
 use flate2::read::DeflateDecoder;
 
-/// The maximum size a metadata chunk is allowed to have.
-/// This limit exists to avoid DoS caused by allocating too much memory.
-pub const REASONABLE_METADATA_LENGTH: u64 = 5 * 1024 * 1024; // 5 MB
-
+/// A metadata chunk
 #[derive(Clone)]
 pub struct Metadata {
     /// name of the chunk (every chunk is assumed to be unique, 4 ascii letters plus terminating 0)
     pub format: Format,
+    /// The decompressed contents, or the stored ones if `inflated` is false
     pub data: Vec<u8>,
+    /// Whether the stored, deflate compressed contents have been inflated
+    pub inflated: bool,
 }
 
 impl Metadata {
-    pub fn all_from_reader<R: Read>(r: &mut R) -> Result<Vec<Metadata>, Error> {
+    /// Reads all chunks, the ones skipped by `options` are left out
+    pub fn all_from_reader<R: Read>(r: &mut R, options: &MetadataOptions, limits: &Limits) -> Result<Vec<Metadata>, Error> {
         // TODO: Maybe create iterator for this?
         let mut result = Vec::new();
+        let mut used = 0;
         loop {
-            let metadata = Self::from_reader(r)?;
-            if let Some(metadata) = metadata {
-                result.push(metadata);
-            } else {
-                return Ok(result);
+            match Self::from_reader(r, options, limits, used)? {
+                Chunk::Metadata(metadata) => {
+                    used += metadata.data.len() as u64;
+                    result.push(metadata);
+                }
+                Chunk::Skipped => {}
+                Chunk::End => return Ok(result),
             }
         }
     }
 
-    pub fn from_reader<R: Read>(r: &mut R) -> Result<Option<Metadata>, Error> {
+    /// Reads a single chunk.
+    /// `used` is how much of the total limit the previous chunks took.
+    pub fn from_reader<R: Read>(r: &mut R, options: &MetadataOptions, limits: &Limits, used: u64) -> Result<Chunk, Error> {
         let mut name = [0; 4];
 
         // Check the first byte
         name[0] = r.read_u8()?;
         if name[0] == 0 || name[0] > 127 {
-            return Ok(None);
+            return Ok(Chunk::End);
         }
         if name[0] < 32 {
             return Err(Error::FutureFormat);
         }
 
         // Check the remaining bytes
-        r.read(&mut name[1..])?;
+        r.read_exact(&mut name[1..])?;
 
         let format = Format::from_bytes(name)?;
 
         let length = r.read_varint().map_err(Error::InvalidLength)?;
-        if length > REASONABLE_METADATA_LENGTH {
-            return Err(Error::UnreasonableLength);
-        }
 
-        // Decompress metadata using deflate
-        let mut data = Vec::new();
-        let mut deflate = DeflateDecoder::new(r.take(length));
-        deflate.read_to_end(&mut data)?;
+        let (data, inflated) = match options.mode(format) {
+            ChunkMode::Skip => {
+                io::copy(&mut r.take(length), &mut io::sink())?;
+                return Ok(Chunk::Skipped);
+            }
+            ChunkMode::Inflate => (limits.read(DeflateDecoder::new(r.take(length)), used)?, true),
+            ChunkMode::Compressed => (limits.read(r.take(length), used)?, false),
+        };
 
-        Ok(Some(Metadata {
+        Ok(Chunk::Metadata(Metadata {
             format: format,
             data: data,
+            inflated: inflated,
         }))
     }
+
+    /// The decompressed contents, inflating them if that didn't happen while reading.
+    /// Inflating fails if the contents get larger than `max_size`.
+    pub fn inflate(&self, max_size: u64) -> Result<Vec<u8>, Error> {
+        let limits = Limits {
+            chunk: max_size,
+            total: max_size,
+        };
+        self.inflate_within(&limits, 0)
+    }
+
+    /// Like `inflate`, but with both limits.
+    /// `used` is how much of the total limit other data takes already.
+    pub fn inflate_within(&self, limits: &Limits, used: u64) -> Result<Vec<u8>, Error> {
+        if self.inflated {
+            return Ok(self.data.clone());
+        }
+
+        limits.read(DeflateDecoder::new(&self.data[..]), used)
+    }
+}
+
+/// How many bytes of metadata are kept in memory at most.
+/// These limits exist to avoid DoS caused by allocating too much memory.
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct Limits {
+    /// For a single chunk
+    pub chunk: u64,
+    /// For all chunks together
+    pub total: u64,
+}
+
+impl Limits {
+    /// Reads everything, unless it is more than the limits allow
+    fn read<R: Read>(&self, r: R, used: u64) -> Result<Vec<u8>, Error> {
+        let remaining = self.total.saturating_sub(used);
+        let max_size = cmp::min(self.chunk, remaining);
+
+        let mut data = Vec::new();
+        r.take(max_size.saturating_add(1)).read_to_end(&mut data)?;
+        if data.len() as u64 <= max_size {
+            Ok(data)
+        } else if self.chunk <= remaining {
+            Err(Error::ChunkLimitExceeded(self.chunk))
+        } else {
+            Err(Error::TotalLimitExceeded(self.total))
+        }
+    }
+}
+
+/// The result of reading a single chunk
+#[derive(Debug)]
+pub enum Chunk {
+    Metadata(Metadata),
+    Skipped,
+    /// There are no more chunks
+    End,
+}
+
+/// What to do with a metadata chunk
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum ChunkMode {
+    Skip,
+    /// Keep the chunk as it is stored
+    Compressed,
+    Inflate,
+}
+
+/// What to do with the metadata chunks of every kind
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub struct MetadataOptions {
+    pub icc: ChunkMode,
+    pub exif: ChunkMode,
+    pub xmp: ChunkMode,
+    pub unknown: ChunkMode,
+}
+
+impl MetadataOptions {
+    /// Uses the same mode for all kinds of chunks
+    pub fn all(mode: ChunkMode) -> Self {
+        MetadataOptions {
+            icc: mode,
+            exif: mode,
+            xmp: mode,
+            unknown: mode,
+        }
+    }
+
+    pub fn mode(&self, format: Format) -> ChunkMode {
+        match format {
+            Format::Icc => self.icc,
+            Format::Exif => self.exif,
+            Format::Xmp => self.xmp,
+            Format::Unknown(_) => self.unknown,
+        }
+    }
+}
+
+impl Default for MetadataOptions {
+    /// Inflates all chunks
+    fn default() -> Self {
+        MetadataOptions::all(ChunkMode::Inflate)
+    }
 }
 
 impl Debug for Metadata {
@@ -69,22 +181,34 @@ impl Debug for Metadata {
     }
 }
 
-#[derive(Debug,Copy,Clone)]
+/// The kind of a metadata chunk
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
 pub enum Format {
+    /// An ICC color profile
     Icc,
+    /// EXIF data
     Exif,
+    /// An XMP document
     Xmp,
+    /// A chunk unknown to this decoder that may be skipped, with its name
+    Unknown([u8; 4]),
 }
 
 impl Format {
+    /// Whether this decoder knows what the chunk contains
+    pub fn is_known(&self) -> bool {
+        !matches!(*self, Format::Unknown(_))
+    }
+
     fn from_bytes(name: [u8; 4]) -> Result<Self, Error> {
         match &name {
             b"iCCP" => Ok(Format::Icc),
             b"eXif" => Ok(Format::Exif),
             b"eXmp" => Ok(Format::Xmp),
+            // Chunks starting with a lowercase letter are optional
             _ =>
-                if name[0] > b'Z' {
-                    Err(Error::UnknownChunk(name))
+                if name[0].is_ascii_lowercase() {
+                    Ok(Format::Unknown(name))
                 } else {
                     Err(Error::UnknownCriticalChunk(name))
                 }
@@ -95,10 +219,6 @@ impl Format {
 quick_error! {
     #[derive(Debug)]
     pub enum Error {
-        UnknownChunk(name: [u8; 4]) {
-            description("Unknown chunk")
-            display("Unknown metadata chunk `{:?}`", name)
-        }
         UnknownCriticalChunk(name: [u8; 4]) {
             description("Unknown critical chunk")
             display("Unknown critical metadata chunk `{:?}`", name)
@@ -107,8 +227,13 @@ quick_error! {
             description("Invalid metadata length")
             cause(err)
         }
-        UnreasonableLength {
-            description("Metadata too big (>5MB)")
+        ChunkLimitExceeded(limit: u64) {
+            description("Metadata chunk exceeds the limit")
+            display("Metadata chunk exceeds the limit of {} bytes per chunk", limit)
+        }
+        TotalLimitExceeded(limit: u64) {
+            description("Metadata exceeds the total limit")
+            display("Metadata exceeds the limit of {} bytes for all chunks", limit)
         }
         FutureFormat {
             description("Not a FLIF16 image, but a more recent FLIF file")
@@ -118,3 +243,68 @@ quick_error! {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        chunk: 100,
+        total: 100,
+    };
+
+    #[test]
+    fn unknown_optional_chunks_are_inflated() {
+        let mut data = &b"abcd\x07\x4b\x29\xcd\xcd\xad\x04\x00\0"[..];
+        let chunks = Metadata::all_from_reader(&mut data, &Default::default(), &LIMITS).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].format, Format::Unknown(*b"abcd"));
+        assert!(chunks[0].inflated);
+        assert_eq!(chunks[0].data, b"dummy");
+    }
+
+    #[test]
+    fn unknown_critical_chunks_fail() {
+        for name in &[b"Abcd", b"[bcd", b"_bcd"] {
+            let mut data = name.to_vec();
+            data.extend_from_slice(b"\x03xyz\0");
+            match Metadata::all_from_reader(&mut &data[..], &Default::default(), &LIMITS) {
+                Err(Error::UnknownCriticalChunk(critical)) => assert_eq!(&critical, *name),
+                other => panic!("expected an unknown critical chunk, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_can_be_skipped_or_kept_compressed() {
+        let stored = b"eXmp\x07\x4b\x29\xcd\xcd\xad\x04\x00abcd\x07\x4b\x29\xcd\xcd\xad\x04\x00\0";
+        let mut options = MetadataOptions::all(ChunkMode::Compressed);
+        options.unknown = ChunkMode::Skip;
+
+        let chunks = Metadata::all_from_reader(&mut &stored[..], &options, &LIMITS).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert!(!chunks[0].inflated);
+        assert_eq!(chunks[0].inflate(5).unwrap(), b"dummy");
+
+        let chunks = Metadata::all_from_reader(&mut &stored[..], &Default::default(), &LIMITS).unwrap();
+        assert_eq!(chunks[0].data, b"dummy");
+        assert_eq!(chunks[1].format, Format::Unknown(*b"abcd"));
+        assert_eq!(chunks[1].data, b"dummy");
+    }
+
+    #[test]
+    fn limits_apply_to_inflated_sizes() {
+        let stored = b"eXmp\x07\x4b\x29\xcd\xcd\xad\x04\x00abcd\x07\x4b\x29\xcd\xcd\xad\x04\x00\0";
+        let chunk = Limits { chunk: 4, total: 100 };
+        match Metadata::all_from_reader(&mut &stored[..], &Default::default(), &chunk) {
+            Err(Error::ChunkLimitExceeded(4)) => {}
+            other => panic!("expected the chunk limit to be hit, got {:?}", other),
+        }
+
+        let total = Limits { chunk: 5, total: 5 };
+        match Metadata::all_from_reader(&mut &stored[..], &Default::default(), &total) {
+            Err(Error::TotalLimitExceeded(5)) => {}
+            other => panic!("expected the total limit to be hit, got {:?}", other),
+        }
+    }
+}
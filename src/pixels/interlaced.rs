@@ -0,0 +1,634 @@
+use std::cmp;
+use std::io::Read;
+use colors::ColorRanges;
+use frame::{ColorVal, Frame};
+use maniac::{rac, UniformSymbolDecoder};
+use maniac::chance::ChanceTable;
+use maniac::tree::PropertyDecoder;
+use transform::LOOKBACK_PLANE;
+use super::{Error, Preview, median3, read_trees, split_frames};
+
+/// The number of zoomlevels (counted from the most detailed one)
+/// that are decoded with the MANIAC trees, unless the file says otherwise.
+/// The others are decoded before the trees have been read.
+const NB_NOLEARN_ZOOMS: usize = 12;
+
+/// The highest interpolation predictor
+const MAX_PREDICTOR: isize = 2;
+
+/// Decodes interlaced pixel data: all planes zoomlevel by zoomlevel, starting
+/// with a single pixel and doubling the resolution in alternating directions.
+/// Decoding stops after `end_zoomlevel`, the other pixels are left untouched.
+///
+/// Truncated files are decoded as far as they go and upscaled from the last
+/// complete zoomlevel, which is returned (`end_zoomlevel` for complete files).
+///
+/// Whenever all planes have been decoded down to another zoomlevel,
+/// `preview` gets that zoomlevel and the frames upscaled to full size.
+pub fn decode_interlaced<C: rac::Config, R: Read>(decoder: &mut UniformSymbolDecoder<C, R>, frames: &mut [Frame], ranges: &dyn ColorRanges, table: &ChanceTable, alpha_zero: bool, end_zoomlevel: usize, preview: Option<Preview>) -> Result<usize, Error> {
+    let n_planes = ranges.num_planes();
+    let zooms = Zoom::new(frames[0].width, frames[0].height).count();
+    let end_zoomlevel = cmp::min(end_zoomlevel, zooms);
+    debug!("decoding zoomlevels {} to {}", zooms, end_zoomlevel);
+
+    // The coarse zoomlevels have too few pixels to learn from,
+    // so they are decoded with trees that consist of a single leaf.
+    let rough_zoomlevel = if decoder.read_bool()? {
+        decoder.read_int(0, zooms as isize)? as usize
+    } else {
+        zooms.saturating_sub(NB_NOLEARN_ZOOMS + 1)
+    };
+    debug!("rough zoomlevel {}", rough_zoomlevel);
+
+    // The first pixel is known before anything else
+    for p in 0..n_planes {
+        if ranges.min(p) < ranges.max(p) {
+            for frame in frames.iter_mut() {
+                frame.planes[p][0] = decoder.read_int(ranges.min(p) as isize, ranges.max(p) as isize)? as ColorVal;
+            }
+        }
+    }
+
+    let mut pass = Pass {
+        ranges: ranges,
+        alpha_zero: alpha_zero,
+        invisible_predictor: 0,
+        predictors: vec![0; n_planes],
+        properties: Vec::new(),
+        reached: vec![zooms + 1; n_planes],
+        previewed: zooms + 1,
+        end_zoomlevel: end_zoomlevel,
+        preview: preview,
+    };
+
+    let mut decoders: Vec<_> = (0..n_planes).map(|_| PropertyDecoder::new(Default::default(), table.clone())).collect();
+    let complete = pass.decode(decoder, frames, &mut decoders, zooms, cmp::max(rough_zoomlevel + 1, end_zoomlevel))?;
+    if complete && end_zoomlevel <= rough_zoomlevel {
+        let decoders = &mut read_trees(decoder.rac(), ranges, table, property_ranges)?;
+        if decoder.rac().garbage_bytes() == 0 {
+            pass.decode(decoder, frames, decoders, rough_zoomlevel, end_zoomlevel)?;
+        }
+    }
+
+    let zoomlevel = cmp::min(pass.zoomlevel(), zooms);
+    if zoomlevel > end_zoomlevel {
+        debug!("truncated at zoomlevel {}", zoomlevel);
+        let zoom = Zoom::new(frames[0].width, frames[0].height).level(zoomlevel);
+        for frame in frames.iter_mut() {
+            *frame = upscale(frame, zoom);
+        }
+    }
+
+    Ok(zoomlevel)
+}
+
+/// The grid of pixels that are known at a zoomlevel
+#[derive(Debug,Copy,Clone)]
+struct Zoom {
+    width: usize,
+    height: usize,
+    /// Vertical distance between the pixels
+    row_step: usize,
+    /// Horizontal distance between the pixels
+    col_step: usize,
+}
+
+impl Zoom {
+    /// The full resolution
+    fn new(width: usize, height: usize) -> Self {
+        Zoom {
+            width: width,
+            height: height,
+            row_step: 1,
+            col_step: 1,
+        }
+    }
+
+    /// Every zoomlevel halves the resolution of the previous one,
+    /// starting with the rows
+    fn level(self, z: usize) -> Self {
+        Zoom {
+            row_step: 1 << ((z + 1) / 2),
+            col_step: 1 << (z / 2),
+            ..self
+        }
+    }
+
+    /// The number of zoomlevels until the image is a single pixel
+    fn count(self) -> usize {
+        let mut z = 0;
+        while self.level(z).rows() > 1 || self.level(z).cols() > 1 {
+            z += 1;
+        }
+        z
+    }
+
+    fn rows(&self) -> usize {
+        1 + (self.height - 1) / self.row_step
+    }
+
+    fn cols(&self) -> usize {
+        1 + (self.width - 1) / self.col_step
+    }
+
+    /// The position of a pixel of this zoomlevel in the full resolution plane
+    fn index(&self, r: usize, c: usize) -> usize {
+        r * self.row_step * self.width + c * self.col_step
+    }
+}
+
+/// The neighbours of a pixel that are known at the time it is decoded
+struct Neighbours<'a> {
+    plane: &'a [ColorVal],
+    zoom: Zoom,
+    r: usize,
+    c: usize,
+}
+
+impl<'a> Neighbours<'a> {
+    fn get(&self, dr: isize, dc: isize) -> ColorVal {
+        let r = (self.r as isize + dr) as usize;
+        let c = (self.c as isize + dc) as usize;
+        self.plane[self.zoom.index(r, c)]
+    }
+
+    fn has_top(&self) -> bool {
+        self.r > 0
+    }
+
+    fn has_bottom(&self) -> bool {
+        self.r + 1 < self.zoom.rows()
+    }
+
+    fn has_left(&self) -> bool {
+        self.c > 0
+    }
+
+    fn has_right(&self) -> bool {
+        self.c + 1 < self.zoom.cols()
+    }
+}
+
+/// The interpolation used for pixels that are not stored
+fn predict(n: &Neighbours, horizontal: bool, p: usize, predictor: u8) -> ColorVal {
+    if p == LOOKBACK_PLANE {
+        return 0;
+    }
+
+    if horizontal {
+        let top = n.get(-1, 0);
+        let bottom = if n.has_bottom() { n.get(1, 0) } else { top };
+        let left = if n.has_left() { n.get(0, -1) } else { top };
+        match predictor {
+            0 => (top + bottom) >> 1,
+            1 => {
+                let top_left = if n.has_left() { n.get(-1, -1) } else { top };
+                let bottom_left = if n.has_left() && n.has_bottom() { n.get(1, -1) } else { left };
+                median3((top + bottom) >> 1, left + top - top_left, left + bottom - bottom_left)
+            }
+            _ => median3(top, bottom, left),
+        }
+    } else {
+        let left = n.get(0, -1);
+        let right = if n.has_right() { n.get(0, 1) } else { left };
+        let top = if n.has_top() { n.get(-1, 0) } else { left };
+        match predictor {
+            0 => (left + right) >> 1,
+            1 => {
+                let top_left = if n.has_top() { n.get(-1, -1) } else { left };
+                let top_right = if n.has_top() && n.has_right() { n.get(-1, 1) } else { top };
+                median3((left + right) >> 1, left + top - top_left, right + top - top_right)
+            }
+            _ => median3(top, left, right),
+        }
+    }
+}
+
+/// The ranges of the properties used by the MANIAC trees of plane `p`
+fn property_ranges(ranges: &dyn ColorRanges, p: usize) -> Vec<(ColorVal, ColorVal)> {
+    let mut property_ranges = Vec::new();
+    let (min, max) = (ranges.min(p), ranges.max(p));
+    let (min_diff, max_diff) = (min - max, max - min);
+
+    if p < 3 {
+        for prev in 0..p {
+            property_ranges.push((ranges.min(prev), ranges.max(prev)));
+        }
+        if ranges.num_planes() > 3 {
+            property_ranges.push((ranges.min(3), ranges.max(3)));
+        }
+    }
+
+    // Which of the interpolations is the median
+    property_ranges.push((0, 2));
+
+    // How far off the interpolation of the luma was
+    if p == 1 || p == 2 {
+        property_ranges.push((ranges.min(0) - ranges.max(0), ranges.max(0) - ranges.min(0)));
+    }
+
+    for _ in 0..4 {
+        property_ranges.push((min_diff, max_diff));
+    }
+    property_ranges.push((min, max));
+
+    if p != 2 {
+        property_ranges.push((min_diff, max_diff));
+        property_ranges.push((min_diff, max_diff));
+    }
+
+    property_ranges
+}
+
+/// Decodes a range of zoomlevels
+struct Pass<'r, 'p> {
+    ranges: &'r dyn ColorRanges,
+    alpha_zero: bool,
+    invisible_predictor: u8,
+    /// The predictor of every plane, or -1 if it is chosen for every zoomlevel
+    predictors: Vec<isize>,
+    properties: Vec<ColorVal>,
+    /// The zoomlevel every plane has been decoded down to
+    reached: Vec<usize>,
+    /// The zoomlevel of the last preview
+    previewed: usize,
+    /// The zoomlevel of the final image, which is not previewed
+    end_zoomlevel: usize,
+    preview: Option<Preview<'p>>,
+}
+
+impl<'r, 'p> Pass<'r, 'p> {
+    /// Returns false if the input ended before all zoomlevels were complete
+    fn decode<C: rac::Config, R: Read>(&mut self, decoder: &mut UniformSymbolDecoder<C, R>, frames: &mut [Frame], decoders: &mut [PropertyDecoder], begin_zoomlevel: usize, end_zoomlevel: usize) -> Result<bool, Error> {
+        if begin_zoomlevel < end_zoomlevel {
+            return Ok(true);
+        }
+
+        let n_planes = self.ranges.num_planes();
+        let steps = n_planes * (begin_zoomlevel - end_zoomlevel + 1);
+        let ordering = PlaneOrdering::new(self.ranges, begin_zoomlevel, end_zoomlevel);
+
+        if self.alpha_zero && n_planes > 3 && self.ranges.min(3) <= 0 {
+            self.invisible_predictor = decoder.read_int(0, MAX_PREDICTOR)? as u8;
+        }
+        for p in 0..n_planes {
+            self.predictors[p] = decoder.read_int(-1, MAX_PREDICTOR)?;
+        }
+
+        for (p, z) in ordering.take(steps) {
+            if self.ranges.min(p) < self.ranges.max(p) {
+                self.decode_step(decoder, frames, &mut decoders[p], p, z)?;
+            }
+
+            // Whatever got decoded from garbage can't be trusted
+            if decoder.rac().garbage_bytes() > 0 {
+                return Ok(false);
+            }
+
+            self.reached[p] = z;
+            self.update_preview(frames);
+        }
+
+        Ok(true)
+    }
+
+    /// The zoomlevel all planes have been decoded down to
+    fn zoomlevel(&self) -> usize {
+        self.reached.iter().cloned().max().unwrap_or(0)
+    }
+
+    /// Decodes plane `p` at zoomlevel `z`
+    fn decode_step<C: rac::Config, R: Read>(&mut self, decoder: &mut UniformSymbolDecoder<C, R>, frames: &mut [Frame], coder: &mut PropertyDecoder, p: usize, z: usize) -> Result<(), Error> {
+        let predictor = match self.predictors[p] {
+            predictor if predictor < 0 => decoder.read_int(0, MAX_PREDICTOR)? as u8,
+            predictor => predictor as u8,
+        };
+        trace!("plane {}, zoomlevel {}: predictor {}", p, z, predictor);
+
+        let zoom = Zoom::new(frames[0].width, frames[0].height).level(z);
+        let horizontal = z % 2 == 0;
+        let rows = if horizontal { (1..zoom.rows()).step_by(2) } else { (0..zoom.rows()).step_by(1) };
+
+        for r in rows {
+            for fr in 0..frames.len() {
+                self.decode_row(decoder.rac(), frames, fr, coder, p, zoom, r, horizontal, predictor)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Hands out a preview once all planes got to a more detailed zoomlevel
+    fn update_preview(&mut self, frames: &[Frame]) {
+        let z = self.zoomlevel();
+        if z >= self.previewed || z <= self.end_zoomlevel {
+            return;
+        }
+        self.previewed = z;
+
+        if let Some(ref mut preview) = self.preview {
+            debug!("preview at zoomlevel {}", z);
+            let zoom = Zoom::new(frames[0].width, frames[0].height).level(z);
+            preview(z, frames.iter().map(|frame| upscale(frame, zoom)).collect());
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn decode_row<C: rac::Config, R: Read>(&mut self, rac: &mut rac::Input<C, R>, frames: &mut [Frame], fr: usize, decoder: &mut PropertyDecoder, p: usize, zoom: Zoom, r: usize, horizontal: bool, predictor: u8) -> Result<(), Error> {
+        let (previous, frame) = split_frames(frames, fr);
+        // The vertical passes only fill the odd columns
+        let (first, step) = if horizontal { (0, 1) } else { (1, 2) };
+        let cols = (first..zoom.cols()).step_by(step);
+
+        if let Some(original) = frame.seen_before {
+            for c in cols {
+                let i = zoom.index(r, c);
+                frame.planes[p][i] = previous[original].planes[p][i];
+            }
+            return Ok(());
+        }
+
+        let full_row = r * zoom.row_step;
+        let mut begin = frame.col_begin[full_row] / zoom.col_step;
+        if !horizontal {
+            // Starts at the odd column left of the shape
+            if begin > 1 && begin % 2 == 0 {
+                begin -= 1;
+            }
+        }
+        let end = (frame.col_end[full_row] + zoom.col_step - 1) / zoom.col_step;
+
+        for c in cols {
+            let i = zoom.index(r, c);
+            // The pixels outside of the shape equal the previous frame,
+            // but invisible ones are predicted like everywhere else
+            let outside = c < begin || c >= end;
+            if outside && p == LOOKBACK_PLANE {
+                frame.planes[p][i] = 0;
+                continue;
+            }
+
+            if self.alpha_zero && p < 3 && frame.planes[3][i] == 0 {
+                let guess = {
+                    let neighbours = Neighbours { plane: &frame.planes[p], zoom: zoom, r: r, c: c };
+                    predict(&neighbours, horizontal, p, self.invisible_predictor)
+                };
+                frame.planes[p][i] = guess;
+                continue;
+            }
+
+            if outside {
+                if let Some(last) = previous.last() {
+                    frame.planes[p][i] = last.planes[p][i];
+                }
+                continue;
+            }
+
+            if frame.planes.len() > LOOKBACK_PLANE && p < LOOKBACK_PLANE && frame.planes[LOOKBACK_PLANE][i] > 0 {
+                let back = frame.planes[LOOKBACK_PLANE][i] as usize;
+                frame.planes[p][i] = previous[fr - back].planes[p][i];
+                continue;
+            }
+
+            let (mut guess, min, mut max) = self.predict_and_compute_properties(frame, p, zoom, r, c, horizontal, predictor);
+            if p == LOOKBACK_PLANE && max > fr as ColorVal {
+                max = fr as ColorVal;
+                if guess > max {
+                    guess = min;
+                }
+            }
+
+            let value = decoder.read_int(rac, &self.properties, min - guess, max - guess)? + guess;
+            frame.planes[p][i] = value;
+        }
+
+        Ok(())
+    }
+
+    /// Fills in the properties of the pixel and returns its guess and range
+    #[allow(clippy::too_many_arguments)]
+    fn predict_and_compute_properties(&mut self, frame: &Frame, p: usize, zoom: Zoom, r: usize, c: usize, horizontal: bool, predictor: u8) -> (ColorVal, ColorVal, ColorVal) {
+        let properties = &mut self.properties;
+        let i = zoom.index(r, c);
+
+        properties.clear();
+        if p < 3 {
+            for prev in 0..p {
+                properties.push(frame.planes[prev][i]);
+            }
+            if self.ranges.num_planes() > 3 {
+                properties.push(frame.planes[3][i]);
+            }
+        }
+
+        let n = Neighbours { plane: &frame.planes[p], zoom: zoom, r: r, c: c };
+        let luma = Neighbours { plane: &frame.planes[0], zoom: zoom, r: r, c: c };
+
+        // Interpolate between the two known neighbours on opposite sides
+        let (guess, which, luma_miss, differences) = if horizontal {
+            let top = n.get(-1, 0);
+            let left = if n.has_left() { n.get(0, -1) } else { top };
+            let bottom = if n.has_bottom() { n.get(1, 0) } else { left };
+            let top_left = if n.has_left() { n.get(-1, -1) } else { top };
+            let top_right = if n.has_right() { n.get(-1, 1) } else { top };
+            let bottom_left = if n.has_left() && n.has_bottom() { n.get(1, -1) } else { left };
+            let bottom_right = if n.has_right() && n.has_bottom() { n.get(1, 1) } else { bottom };
+
+            let average = (top + bottom) >> 1;
+            let top_left_gradient = left + top - top_left;
+            let bottom_left_gradient = left + bottom - bottom_left;
+            let median = median3(average, top_left_gradient, bottom_left_gradient);
+            let which = which(median, average, top_left_gradient);
+            let guess = match predictor {
+                0 => average,
+                1 => median,
+                _ => median3(top, bottom, left),
+            };
+
+            let luma_bottom = if luma.has_bottom() { luma.get(1, 0) } else { luma.get(-1, 0) };
+            let luma_miss = luma.get(0, 0) - ((luma.get(-1, 0) + luma_bottom) >> 1);
+
+            let differences = [
+                top - bottom,
+                top - ((top_left + top_right) >> 1),
+                left - ((bottom_left + top_left) >> 1),
+                bottom - ((bottom_left + bottom_right) >> 1),
+            ];
+
+            (guess, which, luma_miss, differences)
+        } else {
+            let left = n.get(0, -1);
+            let top = if n.has_top() { n.get(-1, 0) } else { left };
+            let right = if n.has_right() { n.get(0, 1) } else { top };
+            let top_left = if n.has_top() { n.get(-1, -1) } else { left };
+            let top_right = if n.has_top() && n.has_right() { n.get(-1, 1) } else { top };
+            let bottom_left = if n.has_bottom() { n.get(1, -1) } else { left };
+            let bottom_right = if n.has_bottom() && n.has_right() { n.get(1, 1) } else { right };
+
+            let average = (left + right) >> 1;
+            let top_left_gradient = left + top - top_left;
+            let top_right_gradient = right + top - top_right;
+            let median = median3(average, top_left_gradient, top_right_gradient);
+            let which = which(median, average, top_left_gradient);
+            let guess = match predictor {
+                0 => average,
+                1 => median,
+                _ => median3(top, left, right),
+            };
+
+            let luma_right = if luma.has_right() { luma.get(0, 1) } else { luma.get(0, -1) };
+            let luma_miss = luma.get(0, 0) - ((luma.get(0, -1) + luma_right) >> 1);
+
+            let differences = [
+                left - right,
+                left - ((bottom_left + top_left) >> 1),
+                top - ((top_left + top_right) >> 1),
+                right - ((bottom_right + top_right) >> 1),
+            ];
+
+            (guess, which, luma_miss, differences)
+        };
+
+        let (min, max, guess) = self.ranges.snap(p, properties, guess);
+
+        properties.push(which);
+        if p == 1 || p == 2 {
+            properties.push(luma_miss);
+        }
+        properties.extend_from_slice(&differences);
+        properties.push(guess);
+
+        if p != 2 {
+            let top = if n.has_top() { n.get(-1, 0) } else { 0 };
+            let left = if n.has_left() { n.get(0, -1) } else { 0 };
+            properties.push(if r > 1 { n.get(-2, 0) - top } else { 0 });
+            properties.push(if c > 1 { n.get(0, -2) - left } else { 0 });
+        }
+
+        (guess, min, max)
+    }
+}
+
+/// Fills every pixel with the closest known one to its top left
+fn upscale(frame: &Frame, zoom: Zoom) -> Frame {
+    let mut upscaled = frame.clone();
+    for (plane, known) in upscaled.planes.iter_mut().zip(&frame.planes) {
+        for r in 0..frame.height {
+            let src_row = r / zoom.row_step * zoom.row_step * frame.width;
+            for c in 0..frame.width {
+                plane[r * frame.width + c] = known[src_row + c / zoom.col_step * zoom.col_step];
+            }
+        }
+    }
+    upscaled
+}
+
+/// Which of the interpolations the median is
+fn which(median: ColorVal, average: ColorVal, gradient: ColorVal) -> ColorVal {
+    if median == average {
+        0
+    } else if median == gradient {
+        1
+    } else {
+        2
+    }
+}
+
+/// The order in which the planes and zoomlevels are decoded.
+///
+/// Each plane is decoded from `begin` to `end` zoomlevel, but the
+/// important planes (lookback, alpha and luma) are allowed to get ahead.
+struct PlaneOrdering {
+    /// The zoomlevel each plane will be decoded at next, plus one
+    zoomlevels: Vec<usize>,
+    max_behind: [usize; 5],
+    highest_priority: usize,
+    end: usize,
+    next: Option<usize>,
+}
+
+impl PlaneOrdering {
+    fn new(ranges: &dyn ColorRanges, begin: usize, end: usize) -> Self {
+        let n_planes = ranges.num_planes();
+        let mut max_behind = [0, 2, 4, 0, 0];
+        // Without luma the chroma planes are the important ones
+        if ranges.min(0) >= ranges.max(0) {
+            max_behind[1] = 0;
+            max_behind[2] = 1;
+        }
+
+        let highest_priority = match n_planes {
+            n if n > LOOKBACK_PLANE => LOOKBACK_PLANE,
+            4 => 3,
+            _ => 0,
+        };
+
+        PlaneOrdering {
+            zoomlevels: vec![begin + 1; n_planes],
+            max_behind: max_behind,
+            highest_priority: highest_priority,
+            end: end,
+            next: None,
+        }
+    }
+}
+
+impl Iterator for PlaneOrdering {
+    /// The plane and its zoomlevel
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        let n_planes = self.zoomlevels.len();
+        let p = match self.next {
+            None => self.highest_priority,
+            Some(_) => {
+                let zoomlevels = &self.zoomlevels;
+                let highest = zoomlevels[self.highest_priority];
+                // The last plane that lags too far behind goes next
+                let mut p = (0..n_planes)
+                    .rev()
+                    .find(|&p| zoomlevels[p] > highest + self.max_behind[p])
+                    .unwrap_or(self.highest_priority);
+
+                // Skip planes that are done already
+                let mut tries = 0;
+                while zoomlevels[p] <= self.end {
+                    p = (p + 1) % n_planes;
+                    tries += 1;
+                    if tries > n_planes {
+                        return None;
+                    }
+                }
+                p
+            }
+        };
+
+        self.next = Some(p);
+        self.zoomlevels[p] -= 1;
+        Some((p, self.zoomlevels[p]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn zoomlevels_end_with_a_single_pixel() {
+        let zoom = Zoom::new(250, 100);
+        let top = zoom.level(zoom.count());
+        assert_eq!((top.rows(), top.cols()), (1, 1));
+        let below = zoom.level(zoom.count() - 1);
+        assert_eq!((below.rows(), below.cols()), (1, 2));
+    }
+
+    #[test]
+    fn upscale_repeats_known_pixels() {
+        let mut frame = Frame::new(3, 2, None);
+        frame.allocate_planes(1);
+        frame.planes[0] = vec![1, 0, 2, 0, 0, 0];
+
+        let upscaled = upscale(&frame, Zoom::new(3, 2).level(2));
+        assert_eq!(upscaled.planes[0], vec![1, 1, 2, 1, 1, 2]);
+    }
+}
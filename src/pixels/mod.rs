@@ -0,0 +1,81 @@
+use std::io::Read;
+use colors::ColorRanges;
+use frame::{ColorVal, Frame};
+use maniac::rac;
+use maniac::chance::ChanceTable;
+use maniac::tree::{self, Tree, PropertyDecoder};
+use maniac::symbol;
+
+mod scanlines;
+mod interlaced;
+
+pub use self::scanlines::decode_scanlines;
+pub use self::interlaced::decode_interlaced;
+
+/// Receives the frames decoded so far, upscaled to full size,
+/// and the zoomlevel they were decoded down to
+pub type Preview<'a> = &'a mut dyn FnMut(usize, Vec<Frame>);
+
+/// The order the planes are decoded in: lookback and alpha first,
+/// so invisible and copied pixels are known before the colors.
+const PLANE_ORDERING: [usize; 5] = [4, 3, 0, 1, 2];
+
+fn median3(a: ColorVal, b: ColorVal, c: ColorVal) -> ColorVal {
+    if a < b {
+        if b < c { b } else if a < c { c } else { a }
+    } else if a < c {
+        a
+    } else if b < c {
+        c
+    } else {
+        b
+    }
+}
+
+/// Reads the MANIAC trees of all planes that are not constant
+fn read_trees<C: rac::Config, R: Read, F>(rac: &mut rac::Input<C, R>, ranges: &dyn ColorRanges, table: &ChanceTable, property_ranges: F) -> Result<Vec<PropertyDecoder>, Error>
+    where F: Fn(&dyn ColorRanges, usize) -> Vec<(ColorVal, ColorVal)>
+{
+    let mut decoders = Vec::new();
+    for p in 0..ranges.num_planes() {
+        let tree = if ranges.min(p) < ranges.max(p) {
+            Tree::read(rac, &property_ranges(ranges, p))?
+        } else {
+            Tree::new()
+        };
+
+        decoders.push(PropertyDecoder::new(tree, table.clone()));
+    }
+
+    Ok(decoders)
+}
+
+/// Splits the frames into the ones before `fr` and frame `fr` itself
+fn split_frames(frames: &mut [Frame], fr: usize) -> (&[Frame], &mut Frame) {
+    let (before, rest) = frames.split_at_mut(fr);
+    (before, &mut rest[0])
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        Tree(err: tree::Error) {
+            from()
+        }
+        Symbol(err: symbol::Error) {
+            from()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn median_of_all_orderings() {
+        for &(a, b, c) in &[(1, 2, 3), (1, 3, 2), (2, 1, 3), (2, 3, 1), (3, 1, 2), (3, 2, 1), (2, 2, 1), (1, 2, 2)] {
+            assert_eq!(median3(a, b, c), 2);
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use std::io::Read;
+use colors::ColorRanges;
+use frame::{ColorVal, Frame};
+use maniac::rac;
+use maniac::chance::ChanceTable;
+use transform::LOOKBACK_PLANE;
+use super::{Error, PLANE_ORDERING, median3, read_trees, split_frames};
+
+/// Decodes non-interlaced pixel data: plane by plane, row by row.
+/// Within a row, all frames are decoded before continuing with the next row.
+///
+/// If the input ends early, decoding stops after the row in which it did and
+/// the plane and row are returned. The samples that weren't decoded are set
+/// to the lowest value of their plane.
+pub fn decode_scanlines<C: rac::Config, R: Read>(rac: &mut rac::Input<C, R>, frames: &mut [Frame], ranges: &dyn ColorRanges, table: &ChanceTable, alpha_zero: bool) -> Result<Option<(usize, usize)>, Error> {
+    let mut decoders = read_trees(rac, ranges, table, property_ranges)?;
+    let n_planes = ranges.num_planes();
+    let lookback = n_planes > LOOKBACK_PLANE;
+    let mut properties = Vec::new();
+
+    // Constant planes have already been filled in
+    let planes: Vec<usize> = PLANE_ORDERING.iter().cloned()
+        .filter(|&p| p < n_planes && ranges.min(p) < ranges.max(p))
+        .collect();
+
+    for (i, &p) in planes.iter().enumerate() {
+        debug!("decoding plane {}", p);
+
+        let fallback = ranges.min(p);
+        let decoder = &mut decoders[p];
+
+        for r in 0..frames[0].height {
+            for fr in 0..frames.len() {
+                let (previous, frame) = split_frames(frames, fr);
+                let width = frame.width;
+                let row = r * width;
+
+                if let Some(original) = frame.seen_before {
+                    let src = &previous[original].planes[p][row..row + width];
+                    frame.planes[p][row..row + width].copy_from_slice(src);
+                    continue;
+                }
+
+                let (begin, end) = (frame.col_begin[r], frame.col_end[r]);
+                for c in 0..width {
+                    let i = row + c;
+                    // The pixels outside of the shape equal the previous frame,
+                    // but invisible ones are predicted like everywhere else
+                    let outside = c < begin || c >= end;
+                    if outside && p == LOOKBACK_PLANE {
+                        frame.planes[p][i] = 0;
+                        continue;
+                    }
+
+                    if alpha_zero && p < 3 && frame.planes[3][i] == 0 {
+                        let guess = predict(&frame.planes[p], width, r, c, fallback);
+                        frame.planes[p][i] = guess;
+                        continue;
+                    }
+
+                    if outside {
+                        if let Some(last) = previous.last() {
+                            frame.planes[p][i] = last.planes[p][i];
+                        }
+                        continue;
+                    }
+
+                    if lookback && p < LOOKBACK_PLANE && frame.planes[LOOKBACK_PLANE][i] > 0 {
+                        let back = frame.planes[LOOKBACK_PLANE][i] as usize;
+                        frame.planes[p][i] = previous[fr - back].planes[p][i];
+                        continue;
+                    }
+
+                    let (guess, min, mut max) = predict_and_compute_properties(&mut properties, ranges, frame, p, r, c, fallback);
+                    if p == LOOKBACK_PLANE && max > fr as ColorVal {
+                        max = fr as ColorVal;
+                    }
+
+                    let value = decoder.read_int(rac, &properties, min - guess, max - guess)? + guess;
+                    frame.planes[p][i] = value;
+                }
+            }
+
+            if rac.garbage_bytes() > 0 {
+                debug!("input ended in row {} of plane {}", r, p);
+                fill_rest(frames, ranges, &planes[i..], r + 1);
+                return Ok(Some((p, r)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Fills the rows from `r` on of the first of the `planes` and all of the others
+fn fill_rest(frames: &mut [Frame], ranges: &dyn ColorRanges, planes: &[usize], r: usize) {
+    for frame in frames {
+        for (i, &p) in planes.iter().enumerate() {
+            let begin = if i == 0 { r * frame.width } else { 0 };
+            for sample in &mut frame.planes[p][begin..] {
+                *sample = ranges.min(p);
+            }
+        }
+    }
+}
+
+/// The ranges of the properties used by the MANIAC trees of plane `p`
+fn property_ranges(ranges: &dyn ColorRanges, p: usize) -> Vec<(ColorVal, ColorVal)> {
+    let mut property_ranges = Vec::new();
+    let (min, max) = (ranges.min(p), ranges.max(p));
+    let (min_diff, max_diff) = (min - max, max - min);
+
+    if p < 3 {
+        for prev in 0..p {
+            property_ranges.push((ranges.min(prev), ranges.max(prev)));
+        }
+        if ranges.num_planes() > 3 {
+            property_ranges.push((ranges.min(3), ranges.max(3)));
+        }
+    }
+
+    // The guess and which predictor it came from
+    property_ranges.push((min, max));
+    property_ranges.push((0, 2));
+
+    // The differences between the neighbours
+    for _ in 0..5 {
+        property_ranges.push((min_diff, max_diff));
+    }
+
+    property_ranges
+}
+
+/// The left, top and top left neighbours, with substitutes at the borders
+fn neighbours(plane: &[ColorVal], width: usize, r: usize, c: usize, fallback: ColorVal) -> (ColorVal, ColorVal, ColorVal) {
+    let i = r * width + c;
+    let left = if c > 0 {
+        plane[i - 1]
+    } else if r > 0 {
+        plane[i - width]
+    } else {
+        fallback
+    };
+    let top = if r > 0 { plane[i - width] } else { left };
+    let top_left = if r > 0 && c > 0 { plane[i - width - 1] } else { top };
+
+    (left, top, top_left)
+}
+
+/// The median of the neighbours left, top and their gradient
+fn predict(plane: &[ColorVal], width: usize, r: usize, c: usize, fallback: ColorVal) -> ColorVal {
+    let (left, top, top_left) = neighbours(plane, width, r, c, fallback);
+    median3(left + top - top_left, left, top)
+}
+
+/// Fills in the `properties` of the pixel and returns its guess and range
+fn predict_and_compute_properties(properties: &mut Vec<ColorVal>, ranges: &dyn ColorRanges, frame: &Frame, p: usize, r: usize, c: usize, fallback: ColorVal) -> (ColorVal, ColorVal, ColorVal) {
+    let width = frame.width;
+    let plane = &frame.planes[p];
+    let i = r * width + c;
+
+    properties.clear();
+    if p < 3 {
+        for prev in 0..p {
+            properties.push(frame.planes[prev][i]);
+        }
+        if ranges.num_planes() > 3 {
+            properties.push(frame.planes[3][i]);
+        }
+    }
+
+    let (left, top, top_left) = neighbours(plane, width, r, c, fallback);
+    let gradient = left + top - top_left;
+
+    let (min, max, guess) = ranges.snap(p, properties, median3(gradient, left, top));
+    let which = if guess == gradient {
+        0
+    } else if guess == left {
+        1
+    } else if guess == top {
+        2
+    } else {
+        0
+    };
+
+    properties.push(guess);
+    properties.push(which);
+
+    if c > 0 && r > 0 {
+        properties.push(left - top_left);
+        properties.push(top_left - top);
+    } else {
+        properties.push(0);
+        properties.push(0);
+    }
+
+    if c + 1 < width && r > 0 {
+        properties.push(top - plane[i - width + 1]);
+    } else {
+        properties.push(0);
+    }
+
+    if r > 1 {
+        properties.push(plane[i - 2 * width] - top);
+    } else {
+        properties.push(0);
+    }
+
+    if c > 1 {
+        properties.push(plane[i - 2] - left);
+    } else {
+        properties.push(0);
+    }
+
+    (guess, min, max)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use colors::StaticColorRanges;
+    use maniac::encoder::{Output, PropertyEncoder, SimpleSymbolEncoder};
+    use maniac::rac::Input24;
+    use maniac::tree::Tree;
+
+    /// Writes a tree whose root splits on `property` after `count` pixels,
+    /// or a single leaf without a split
+    fn write_tree(output: &mut Output, property_ranges: &[(ColorVal, ColorVal)], split: Option<(usize, i32, i32)>) {
+        let mut properties = SimpleSymbolEncoder::new();
+        let mut counts = SimpleSymbolEncoder::new();
+        let mut splits = SimpleSymbolEncoder::new();
+        let n_properties = property_ranges.len() as i32;
+
+        if let Some((property, count, splitval)) = split {
+            let (min, max) = property_ranges[property];
+            properties.write_int(output, 0, n_properties, property as i32 + 1);
+            counts.write_int(output, 1, 512, count);
+            splits.write_int(output, min, max - 1, splitval);
+            // Both children are leaves
+            properties.write_int(output, 0, n_properties, 0);
+        }
+        properties.write_int(output, 0, n_properties, 0);
+    }
+
+    /// Encodes the trees and pixels of `image` like a non-interlaced FLIF
+    fn encode(image: &Frame, ranges: &dyn ColorRanges, splits: &[Option<(usize, i32, i32)>]) -> Vec<u8> {
+        let mut output = Output::new();
+        let mut encoders = Vec::new();
+        for (p, &split) in splits.iter().enumerate() {
+            write_tree(&mut output, &property_ranges(ranges, p), split);
+
+            // The encoder needs to pick the same leaves as the decoder
+            let mut tree = Output::new();
+            write_tree(&mut tree, &property_ranges(ranges, p), split);
+            let tree = tree.finish();
+            let tree = Tree::read(&mut Input24::new(&tree[..]).unwrap(), &property_ranges(ranges, p)).unwrap();
+            encoders.push(PropertyEncoder::new(tree));
+        }
+
+        let mut properties = Vec::new();
+        for (p, encoder) in encoders.iter_mut().enumerate() {
+            for r in 0..image.height {
+                for c in 0..image.width {
+                    let value = image.planes[p][r * image.width + c];
+                    let (guess, min, max) = predict_and_compute_properties(&mut properties, ranges, image, p, r, c, ranges.min(p));
+                    encoder.write_int(&mut output, &properties, min - guess, max - guess, value - guess);
+                }
+            }
+        }
+
+        output.finish()
+    }
+
+    #[test]
+    fn decodes_predicted_pixels_with_split_trees() {
+        let ranges = StaticColorRanges::new(vec![(0, 255); 3]);
+        let mut image = Frame::new(5, 4, None);
+        image.planes = vec![
+            vec![0, 10, 20, 30, 40, 12, 25, 180, 200, 41, 30, 240, 255, 210, 100, 35, 5, 77, 130, 131],
+            vec![100, 100, 100, 0, 255, 99, 98, 50, 51, 52, 1, 2, 3, 4, 5, 200, 201, 0, 0, 7],
+            vec![9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 8, 9, 9, 9, 255, 0, 255, 0, 255],
+        ];
+
+        // The first plane splits on the guess, the second on the first plane
+        let data = encode(&image, &ranges, &[Some((0, 3, 100)), Some((0, 1, 50)), None]);
+
+        let mut frames = vec![Frame::new(5, 4, None)];
+        frames[0].allocate_planes(3);
+        let mut rac = Input24::new(&data[..]).unwrap();
+        let progress = decode_scanlines(&mut rac, &mut frames, &ranges, &ChanceTable::default(), false).unwrap();
+
+        assert_eq!(progress, None);
+        assert_eq!(frames[0].planes, image.planes);
+    }
+}
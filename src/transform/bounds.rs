@@ -0,0 +1,125 @@
+use std::cmp;
+use std::io::Read;
+use colors::{ColorRanges, StaticColorRanges};
+use frame::{ColorVal, Frame};
+use maniac::{rac, UniformSymbolDecoder};
+use maniac::symbol::SimpleSymbolDecoder;
+use super::{Error, Transform, Transformation};
+
+/// Narrows the range of every plane to the values that actually occur
+#[derive(Debug)]
+pub struct Bounds {
+    bounds: Vec<(ColorVal, ColorVal)>,
+}
+
+impl Bounds {
+    pub fn read<C: rac::Config, R: Read>(decoder: &mut UniformSymbolDecoder<C, R>, ranges: &dyn ColorRanges) -> Result<Self, Error> {
+        let mut coder = SimpleSymbolDecoder::default();
+        let rac = decoder.rac();
+        let mut bounds = Vec::new();
+
+        for p in 0..ranges.num_planes() {
+            let (src_min, src_max) = (ranges.min(p), ranges.max(p));
+            if src_min > src_max {
+                return Err(Error::Invalid(Transformation::Bounds));
+            }
+
+            // Chroma ranges after YCoCg contain 0 and are read as signed numbers,
+            // the others relative to their end closest to 0
+            let min = coder.read_int(rac, src_min, src_max)?;
+            let max = coder.read_int(rac, min, src_max)?;
+            trace!("bounds of plane {}: {}..={}", p, min, max);
+
+            bounds.push((min, max));
+        }
+
+        Ok(Bounds {
+            bounds: bounds,
+        })
+    }
+}
+
+impl Transform for Bounds {
+    fn kind(&self) -> Transformation {
+        Transformation::Bounds
+    }
+
+    fn ranges(&self, src: Box<dyn ColorRanges>) -> Box<dyn ColorRanges> {
+        if src.is_static() {
+            return Box::new(StaticColorRanges::new(self.bounds.clone()));
+        }
+
+        Box::new(BoundsRanges {
+            bounds: self.bounds.clone(),
+            src: src,
+        })
+    }
+
+    fn undo(&self, _frames: &mut [Frame]) {
+        // The values already lie in the original ranges
+    }
+}
+
+#[derive(Debug)]
+struct BoundsRanges {
+    bounds: Vec<(ColorVal, ColorVal)>,
+    src: Box<dyn ColorRanges>,
+}
+
+impl ColorRanges for BoundsRanges {
+    fn num_planes(&self) -> usize {
+        self.src.num_planes()
+    }
+
+    fn min(&self, p: usize) -> ColorVal {
+        cmp::max(self.src.min(p), self.bounds[p].0)
+    }
+
+    fn max(&self, p: usize) -> ColorVal {
+        cmp::min(self.src.max(p), self.bounds[p].1)
+    }
+
+    fn minmax(&self, p: usize, prev: &[ColorVal]) -> (ColorVal, ColorVal) {
+        let (bound_min, bound_max) = self.bounds[p];
+        // The luma and alpha ranges never depend on other planes
+        if p == 0 || p == 3 {
+            return (bound_min, bound_max);
+        }
+
+        let (min, max) = self.src.minmax(p, prev);
+        let (min, max) = (cmp::max(min, bound_min), cmp::min(max, bound_max));
+        if min > max {
+            // Disjoint ranges should not happen, fall back to the bounds
+            return (bound_min, bound_max);
+        }
+
+        (min, max)
+    }
+
+    fn is_static(&self) -> bool {
+        self.src.is_static()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maniac::rac::Input24;
+    use transform::YCoCg;
+
+    fn read_after_ycocg(data: &[u8]) -> Vec<(ColorVal, ColorVal)> {
+        let src = StaticColorRanges::new(vec![(0, 255); 4]);
+        let ranges = YCoCg::new(&src).unwrap().ranges(Box::new(src));
+        let mut decoder = UniformSymbolDecoder::new(Input24::new(data).unwrap());
+        Bounds::read(&mut decoder, &*ranges).unwrap().bounds
+    }
+
+    #[test]
+    fn reads_chroma_bounds_after_ycocg() {
+        // Only 1 bits: every number is as close to 0 as its range allows
+        assert_eq!(read_after_ycocg(&[0xFF; 64]), vec![(0, 0); 4]);
+
+        // Only 0 bits: the chroma ranges contain 0, so their sign is read
+        assert_eq!(read_after_ycocg(&[0; 64]), vec![(128, 192), (-128, -128), (-128, -128), (128, 192)]);
+    }
+}
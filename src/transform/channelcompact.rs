@@ -0,0 +1,125 @@
+use std::io::Read;
+use colors::{ColorRanges, StaticColorRanges};
+use frame::{ColorVal, Frame};
+use maniac::{rac, UniformSymbolDecoder};
+use maniac::symbol::SimpleSymbolDecoder;
+use super::{Error, Transform, Transformation};
+
+/// Replaces the values of every plane by indices into a list of the values that occur
+#[derive(Debug)]
+pub struct ChannelCompact {
+    /// The values of every plane in ascending order
+    values: Vec<Vec<ColorVal>>,
+}
+
+impl ChannelCompact {
+    pub fn read<C: rac::Config, R: Read>(decoder: &mut UniformSymbolDecoder<C, R>, ranges: &dyn ColorRanges) -> Result<Self, Error> {
+        let mut coder = SimpleSymbolDecoder::default();
+        let rac = decoder.rac();
+        let mut values = Vec::new();
+
+        for p in 0..ranges.num_planes() {
+            let max = ranges.max(p);
+            let mut min = ranges.min(p);
+            if min > max {
+                return Err(Error::Invalid(Transformation::ChannelCompact));
+            }
+
+            let n = coder.read_int(rac, 0, max - min)? + 1;
+            trace!("plane {} has {} values", p, n);
+
+            let mut plane_values = Vec::with_capacity(n as usize);
+            // Leave room for the values that are still to come
+            for remaining in (0..n).rev() {
+                let value = min + coder.read_int(rac, 0, max - min - remaining)?;
+                plane_values.push(value);
+                min = value + 1;
+            }
+
+            values.push(plane_values);
+        }
+
+        Ok(ChannelCompact {
+            values: values,
+        })
+    }
+}
+
+impl Transform for ChannelCompact {
+    fn kind(&self) -> Transformation {
+        Transformation::ChannelCompact
+    }
+
+    fn ranges(&self, _src: Box<dyn ColorRanges>) -> Box<dyn ColorRanges> {
+        let ranges = self.values.iter()
+            .map(|values| (0, values.len() as ColorVal - 1))
+            .collect();
+
+        Box::new(StaticColorRanges::new(ranges))
+    }
+
+    fn undo(&self, frames: &mut [Frame]) {
+        for frame in frames {
+            for (plane, values) in frame.planes.iter_mut().zip(&self.values) {
+                for sample in plane {
+                    let index = *sample;
+                    // Corrupt indices fall back to the first value
+                    *sample = if index < 0 || index as usize >= values.len() {
+                        values[0]
+                    } else {
+                        values[index as usize]
+                    };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maniac::encoder::{Output, SimpleSymbolEncoder};
+    use maniac::rac::Input24;
+
+    #[test]
+    fn reads_ascending_values() {
+        let src = StaticColorRanges::new(vec![(0, 255), (10, 13)]);
+        let planes = vec![vec![3, 7, 250, 255], vec![10, 11, 12, 13]];
+
+        // Every value leaves room for the ones that are still to come
+        let mut output = Output::new();
+        let mut coder = SimpleSymbolEncoder::new();
+        for (p, values) in planes.iter().enumerate() {
+            let (mut min, max) = (src.min(p), src.max(p));
+            let n = values.len() as ColorVal;
+            coder.write_int(&mut output, 0, max - min, n - 1);
+            for (remaining, &value) in (0..n).rev().zip(values) {
+                coder.write_int(&mut output, 0, max - min - remaining, value - min);
+                min = value + 1;
+            }
+        }
+        let data = output.finish();
+
+        let mut decoder = UniformSymbolDecoder::new(Input24::new(&data[..]).unwrap());
+        let transform = ChannelCompact::read(&mut decoder, &src).unwrap();
+        assert_eq!(transform.values, planes);
+
+        let ranges = transform.ranges(Box::new(src));
+        assert_eq!((ranges.min(0), ranges.max(0)), (0, 3));
+        assert_eq!((ranges.min(1), ranges.max(1)), (0, 3));
+    }
+
+    #[test]
+    fn undo_looks_up_the_indices() {
+        let transform = ChannelCompact {
+            values: vec![vec![3, 7, 250], vec![10]],
+        };
+        let mut frames = vec![Frame::new(3, 1, None)];
+        frames[0].allocate_planes(2);
+        // An index beyond the values falls back to the first one
+        frames[0].planes[0] = vec![2, 0, 5];
+        transform.undo(&mut frames);
+
+        assert_eq!(frames[0].planes, vec![vec![250, 3, 3], vec![10, 10, 10]]);
+    }
+}
@@ -0,0 +1,410 @@
+use std::io::Read;
+use std::rc::Rc;
+use colors::{self, ColorRanges};
+use frame::{ColorVal, Frame};
+use maniac::{rac, UniformSymbolDecoder};
+use maniac::symbol::SimpleSymbolDecoder;
+use super::{Error, Transform, Transformation};
+
+/// The maximum number of discrete values a bucket of each plane can have
+const MAX_PER_BUCKET: [ColorVal; 4] = [255, 510, 5, 255];
+
+/// The maximum number of buckets, which is far more than 8 bit planes need
+const MAX_BUCKETS: usize = 1 << 20;
+
+/// The values a plane can take for a given combination of previous planes
+#[derive(Debug,Clone)]
+struct Bucket {
+    min: ColorVal,
+    max: ColorVal,
+    /// Only the `values` can occur, instead of everything in `min..=max`
+    discrete: bool,
+    values: Vec<ColorVal>,
+    /// The closest of the `values` for everything in `min..=max`
+    snap_values: Vec<ColorVal>,
+}
+
+impl Bucket {
+    fn empty() -> Self {
+        Bucket {
+            min: 10_000,
+            max: -10_000,
+            discrete: true,
+            values: Vec::new(),
+            snap_values: Vec::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.min > self.max
+    }
+
+    fn snap(&self, value: ColorVal) -> ColorVal {
+        if value <= self.min {
+            self.min
+        } else if value >= self.max {
+            self.max
+        } else if self.discrete {
+            self.snap_values[(value - self.min) as usize]
+        } else {
+            value
+        }
+    }
+
+    fn snap_slow(&self, value: ColorVal) -> ColorVal {
+        if value <= self.min {
+            return self.min;
+        }
+        if value >= self.max {
+            return self.max;
+        }
+
+        let mut best = self.values[0];
+        let mut best_diff = (value - best).abs();
+        for &candidate in &self.values[1..] {
+            let diff = (value - candidate).abs();
+            if diff < best_diff {
+                best = candidate;
+                best_diff = diff;
+            }
+            if candidate >= value {
+                break;
+            }
+        }
+
+        best
+    }
+
+    fn prepare_snap_values(&mut self) {
+        self.snap_values = (self.min..self.max + 1)
+            .map(|value| self.snap_slow(value))
+            .collect();
+    }
+}
+
+/// Buckets for Y, for Co per Y, for Cg per Y and group of 4 Co values, and for alpha
+#[derive(Debug)]
+struct Buckets {
+    min0: ColorVal,
+    min1: ColorVal,
+    bucket0: Bucket,
+    bucket1: Vec<Bucket>,
+    bucket2: Vec<Vec<Bucket>>,
+    bucket3: Bucket,
+    empty: Bucket,
+}
+
+impl Buckets {
+    fn new(ranges: &dyn ColorRanges) -> Result<Self, Error> {
+        let n_y = (ranges.max(0) - ranges.min(0) + 1) as usize;
+        let n_co = ((ranges.max(1) - ranges.min(1)) / 4 + 1) as usize;
+        if n_y.saturating_mul(n_co) > MAX_BUCKETS {
+            return Err(Error::Invalid(Transformation::ColorBuckets));
+        }
+
+        Ok(Buckets {
+            min0: ranges.min(0),
+            min1: ranges.min(1),
+            bucket0: Bucket::empty(),
+            bucket1: vec![Bucket::empty(); n_y],
+            bucket2: vec![vec![Bucket::empty(); n_co]; n_y],
+            bucket3: Bucket::empty(),
+            empty: Bucket::empty(),
+        })
+    }
+
+    fn find(&self, p: usize, prev: &[ColorVal]) -> &Bucket {
+        let index = |value: ColorVal, min: ColorVal, div: ColorVal| {
+            if value < min { None } else { Some(((value - min) / div) as usize) }
+        };
+
+        let bucket = match p {
+            0 => Some(&self.bucket0),
+            1 => index(prev[0], self.min0, 1).and_then(|y| self.bucket1.get(y)),
+            2 => index(prev[0], self.min0, 1)
+                .and_then(|y| self.bucket2.get(y))
+                .and_then(|buckets| index(prev[1], self.min1, 4).and_then(|co| buckets.get(co))),
+            3 => Some(&self.bucket3),
+            _ => None,
+        };
+
+        bucket.unwrap_or(&self.empty)
+    }
+
+    /// Whether plane `p` can take the value in `pixel`
+    fn contains(&self, src: &dyn ColorRanges, p: usize, pixel: &[ColorVal]) -> bool {
+        let value = pixel[p];
+        let (_, _, snapped) = src.snap(p, pixel, value);
+        if snapped != value {
+            return false;
+        }
+
+        self.find(p, pixel).snap(value) == value
+    }
+
+    /// Whether plane `p` can take any value in `lower[p]..=upper[p]`
+    fn exists(&self, src: &dyn ColorRanges, p: usize, lower: &[ColorVal], upper: &[ColorVal]) -> bool {
+        let mut pixel = lower.to_vec();
+        for value in lower[p]..upper[p] + 1 {
+            pixel[p] = value;
+            if self.contains(src, p, &pixel) {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+/// Restricts the values of each plane depending on the previous planes
+#[derive(Debug)]
+pub struct ColorBuckets {
+    buckets: Rc<Buckets>,
+}
+
+impl ColorBuckets {
+    pub fn read<C: rac::Config, R: Read>(decoder: &mut UniformSymbolDecoder<C, R>, ranges: &dyn ColorRanges) -> Result<Self, Error> {
+        if ranges.num_planes() < 3 || ranges.min(1) == ranges.max(1) || ranges.min(2) == ranges.max(2) {
+            return Err(Error::Invalid(Transformation::ColorBuckets));
+        }
+
+        let mut loader = Loader {
+            coders: Default::default(),
+            rac: decoder.rac(),
+            src: ranges,
+            buckets: Buckets::new(ranges)?,
+        };
+
+        let mut lower = [0; 2];
+        let mut upper = [0; 2];
+        loader.load(0, &lower, &upper, |buckets| &mut buckets.bucket0)?;
+
+        for y in ranges.min(0)..ranges.max(0) + 1 {
+            lower[0] = y;
+            upper[0] = y;
+            let index = (y - loader.buckets.min0) as usize;
+            loader.load(1, &lower, &upper, |buckets| &mut buckets.bucket1[index])?;
+        }
+
+        for y in ranges.min(0)..ranges.max(0) + 1 {
+            lower[0] = y;
+            upper[0] = y;
+            let index = (y - loader.buckets.min0) as usize;
+            for i in 0..loader.buckets.bucket2[index].len() {
+                lower[1] = loader.buckets.min1 + 4 * i as ColorVal;
+                upper[1] = lower[1] + 3;
+                loader.load(2, &lower, &upper, |buckets| &mut buckets.bucket2[index][i])?;
+            }
+        }
+
+        if ranges.num_planes() > 3 {
+            loader.load(3, &lower, &upper, |buckets| &mut buckets.bucket3)?;
+        }
+
+        Ok(ColorBuckets {
+            buckets: Rc::new(loader.buckets),
+        })
+    }
+}
+
+struct Loader<'a, C: rac::Config + 'a, R: 'a> {
+    /// For the existence and discrete flags, the minimum, the maximum,
+    /// the number of values and the values themselves
+    coders: [SimpleSymbolDecoder; 5],
+    rac: &'a mut rac::Input<C, R>,
+    src: &'a dyn ColorRanges,
+    buckets: Buckets,
+}
+
+impl<'a, C: rac::Config, R: Read> Loader<'a, C, R> {
+    fn load<F>(&mut self, p: usize, lower: &[ColorVal], upper: &[ColorVal], bucket: F) -> Result<(), Error>
+        where F: FnOnce(&mut Buckets) -> &mut Bucket
+    {
+        // Nothing is stored for combinations that cannot occur, but alpha
+        // doesn't depend on the other planes
+        if p < 3 {
+            for prev in 0..p {
+                if !self.buckets.exists(self.src, prev, lower, upper) {
+                    return Ok(());
+                }
+            }
+        }
+
+        let (src_min, src_max) = src_range(self.src, p, lower, upper);
+        let rac = &mut *self.rac;
+        let coders = &mut self.coders;
+        let bucket = bucket(&mut self.buckets);
+
+        if !coders[0].read_bool(rac)? {
+            return Ok(());
+        }
+
+        // Like everywhere else, ranges that don't contain 0 are read relative to
+        // the end closest to it, and the others as signed values
+        bucket.min = coders[1].read_int(rac, src_min, src_max)?;
+        bucket.max = coders[2].read_int(rac, bucket.min, src_max)?;
+        if bucket.max - bucket.min < 2 {
+            bucket.discrete = false;
+            return Ok(());
+        }
+
+        bucket.discrete = coders[0].read_bool(rac)?;
+        if bucket.discrete {
+            let n = coders[3].read_int(rac, 2, MAX_PER_BUCKET[p].min(bucket.max - bucket.min))?;
+            let mut value = bucket.min;
+            bucket.values.push(value);
+            for i in 1..n - 1 {
+                value = coders[4].read_int(rac, value + 1, bucket.max + 1 - n + i)?;
+                bucket.values.push(value);
+            }
+            bucket.values.push(bucket.max);
+            bucket.prepare_snap_values();
+        }
+
+        Ok(())
+    }
+}
+
+/// The range of plane `p` in the source for any previous planes in `lower..=upper`
+fn src_range(src: &dyn ColorRanges, p: usize, lower: &[ColorVal], upper: &[ColorVal]) -> (ColorVal, ColorVal) {
+    if p != 1 && p != 2 {
+        return (src.min(p), src.max(p));
+    }
+
+    let (mut min, mut max) = (ColorVal::MAX, ColorVal::MIN);
+    let mut prev = lower.to_vec();
+    for y in lower[0]..upper[0] + 1 {
+        prev[0] = y;
+        let cos = if p == 2 { lower[1]..upper[1] + 1 } else { lower[1]..lower[1] + 1 };
+        for co in cos {
+            prev[1] = co;
+            let (prev_min, prev_max) = src.minmax(p, &prev);
+            min = min.min(prev_min);
+            max = max.max(prev_max);
+        }
+    }
+
+    (min, max)
+}
+
+impl Transform for ColorBuckets {
+    fn kind(&self) -> Transformation {
+        Transformation::ColorBuckets
+    }
+
+    fn ranges(&self, src: Box<dyn ColorRanges>) -> Box<dyn ColorRanges> {
+        Box::new(ColorBucketsRanges {
+            buckets: self.buckets.clone(),
+            src: src,
+        })
+    }
+
+    fn undo(&self, _frames: &mut [Frame]) {
+        // Only values from the buckets have been decoded
+    }
+}
+
+#[derive(Debug)]
+struct ColorBucketsRanges {
+    buckets: Rc<Buckets>,
+    src: Box<dyn ColorRanges>,
+}
+
+impl ColorRanges for ColorBucketsRanges {
+    fn num_planes(&self) -> usize {
+        self.src.num_planes()
+    }
+
+    fn min(&self, p: usize) -> ColorVal {
+        self.src.min(p)
+    }
+
+    fn max(&self, p: usize) -> ColorVal {
+        self.src.max(p)
+    }
+
+    fn minmax(&self, p: usize, prev: &[ColorVal]) -> (ColorVal, ColorVal) {
+        let bucket = self.buckets.find(p, prev);
+        if bucket.is_empty() {
+            warn!("Corruption detected: empty color bucket in plane {}", p);
+            return (self.min(p), self.max(p));
+        }
+
+        (bucket.min, bucket.max)
+    }
+
+    fn snap(&self, p: usize, prev: &[ColorVal], guess: ColorVal) -> (ColorVal, ColorVal, ColorVal) {
+        let bucket = self.buckets.find(p, prev);
+        if bucket.is_empty() {
+            warn!("Corruption detected: empty color bucket in plane {}", p);
+            let (min, max) = (self.min(p), self.max(p));
+            return (min, max, colors::clamp(guess, min, max));
+        }
+
+        (bucket.min, bucket.max, bucket.snap(guess))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use colors::StaticColorRanges;
+    use maniac::rac::Input24;
+    use transform::YCoCg;
+
+    fn ycocg(planes: Vec<(ColorVal, ColorVal)>) -> Box<dyn ColorRanges> {
+        let src = StaticColorRanges::new(planes);
+        YCoCg::new(&src).unwrap().ranges(Box::new(src))
+    }
+
+    #[test]
+    fn reads_alpha_bucket() {
+        let ranges = ycocg(vec![(0, 255); 4]);
+        // Only 0 bits: every bucket that can occur exists
+        let data = vec![0; 1 << 16];
+        let mut decoder = UniformSymbolDecoder::new(Input24::new(&data[..]).unwrap());
+        let transform = ColorBuckets::read(&mut decoder, &*ranges).unwrap();
+
+        let buckets = &transform.buckets;
+        assert!(!buckets.bucket0.is_empty());
+        assert!(!buckets.bucket3.is_empty());
+        assert_eq!((buckets.bucket3.min, buckets.bucket3.max), (buckets.bucket0.min, buckets.bucket0.max));
+    }
+
+    #[test]
+    fn cg_range_covers_group_of_co_values() {
+        let ranges = ycocg(vec![(0, 255); 3]);
+        let (y, co) = (10, -8);
+
+        let mut expected = (ColorVal::MAX, ColorVal::MIN);
+        for co in co..co + 4 {
+            let (min, max) = ranges.minmax(2, &[y, co]);
+            expected = (expected.0.min(min), expected.1.max(max));
+        }
+
+        assert_eq!(src_range(&*ranges, 2, &[y, co], &[y, co + 3]), expected);
+        assert!(expected != ranges.minmax(2, &[y, co]));
+    }
+
+    #[test]
+    fn rejects_too_many_buckets() {
+        let ranges = ycocg(vec![(0, 65535); 3]);
+        let mut decoder = UniformSymbolDecoder::new(Input24::new(&[0xFF; 64][..]).unwrap());
+        match ColorBuckets::read(&mut decoder, &*ranges) {
+            Err(Error::Invalid(Transformation::ColorBuckets)) => {}
+            other => panic!("expected the bucket limit to be hit, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn discrete_bucket_snaps_to_closest_value() {
+        let mut bucket = Bucket::empty();
+        bucket.min = 2;
+        bucket.max = 10;
+        bucket.values = vec![2, 5, 10];
+        bucket.prepare_snap_values();
+
+        let snapped: Vec<_> = (0..12).map(|value| bucket.snap(value)).collect();
+        assert_eq!(snapped, vec![2, 2, 2, 2, 5, 5, 5, 5, 10, 10, 10, 10]);
+    }
+}
@@ -0,0 +1,68 @@
+use std::io::Read;
+use colors::ColorRanges;
+use frame::Frame;
+use maniac::{rac, UniformSymbolDecoder};
+use maniac::symbol::SimpleSymbolDecoder;
+use super::{Error, Transform, Transformation};
+
+/// Stores frames that are identical to an earlier one only once
+#[derive(Debug)]
+pub struct DuplicateFrame {
+    seen_before: Vec<Option<usize>>,
+}
+
+impl DuplicateFrame {
+    pub fn read<C: rac::Config, R: Read>(decoder: &mut UniformSymbolDecoder<C, R>, frames: &[Frame]) -> Result<Self, Error> {
+        let mut coder = SimpleSymbolDecoder::default();
+        let rac = decoder.rac();
+
+        let mut seen_before = vec![None];
+        for i in 1..frames.len() {
+            let frame = coder.read_int(rac, -1, i as i32 - 1)?;
+            seen_before.push(if frame < 0 { None } else { Some(frame as usize) });
+        }
+        debug!("{} duplicate frames", seen_before.iter().filter(|frame| frame.is_some()).count());
+
+        Ok(DuplicateFrame {
+            seen_before: seen_before,
+        })
+    }
+}
+
+impl Transform for DuplicateFrame {
+    fn kind(&self) -> Transformation {
+        Transformation::DuplicateFrame
+    }
+
+    fn ranges(&self, src: Box<dyn ColorRanges>) -> Box<dyn ColorRanges> {
+        src
+    }
+
+    fn configure(&self, frames: &mut [Frame]) {
+        for (frame, &seen_before) in frames.iter_mut().zip(&self.seen_before) {
+            frame.seen_before = seen_before;
+        }
+    }
+
+    fn undo(&self, _frames: &mut [Frame]) {
+        // Duplicates are copied while decoding
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maniac::rac::Input24;
+
+    #[test]
+    fn marks_frames_as_seen_before() {
+        let mut frames: Vec<_> = (0..3).map(|_| Frame::new(2, 2, None)).collect();
+        // Only 1 bits: every frame after the first is a copy of it
+        let mut decoder = UniformSymbolDecoder::new(Input24::new(&[0xFF; 64][..]).unwrap());
+        let transform = DuplicateFrame::read(&mut decoder, &frames).unwrap();
+        transform.configure(&mut frames);
+
+        let seen_before: Vec<_> = frames.iter().map(|frame| frame.seen_before).collect();
+        assert_eq!(seen_before, vec![None, Some(0), Some(0)]);
+    }
+}
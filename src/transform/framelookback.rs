@@ -0,0 +1,137 @@
+use std::io::Read;
+use colors::ColorRanges;
+use frame::{ColorVal, Frame};
+use maniac::{rac, UniformSymbolDecoder};
+use maniac::symbol::SimpleSymbolDecoder;
+use super::{Error, Transform, Transformation};
+
+/// The plane that holds how many frames back a pixel is copied from
+pub const LOOKBACK_PLANE: usize = 4;
+
+/// Allows pixels to be copied from one of the previous frames
+#[derive(Debug)]
+pub struct FrameLookback {
+    max_lookback: ColorVal,
+    /// The number of planes before the lookback plane was added
+    n_planes: usize,
+}
+
+impl FrameLookback {
+    pub fn read<C: rac::Config, R: Read>(decoder: &mut UniformSymbolDecoder<C, R>, ranges: &dyn ColorRanges, frames: &[Frame]) -> Result<Self, Error> {
+        // The ranges of the color planes come from the source
+        if frames.len() < 2 || ranges.num_planes() < 3 || ranges.num_planes() > LOOKBACK_PLANE {
+            return Err(Error::Invalid(Transformation::FrameLookback));
+        }
+
+        let mut coder = SimpleSymbolDecoder::default();
+        let max_lookback = coder.read_int(decoder.rac(), 1, frames.len() as i32 - 1)?;
+        debug!("frames look back up to {} frames", max_lookback);
+
+        Ok(FrameLookback {
+            max_lookback: max_lookback,
+            n_planes: ranges.num_planes(),
+        })
+    }
+}
+
+impl Transform for FrameLookback {
+    fn kind(&self) -> Transformation {
+        Transformation::FrameLookback
+    }
+
+    fn ranges(&self, src: Box<dyn ColorRanges>) -> Box<dyn ColorRanges> {
+        // Images without alpha get a fully opaque one
+        let alpha = if src.num_planes() > 3 {
+            (src.min(3), src.max(3))
+        } else {
+            (1, 1)
+        };
+
+        Box::new(FrameLookbackRanges {
+            max_lookback: self.max_lookback,
+            alpha: alpha,
+            src: src,
+        })
+    }
+
+    fn undo(&self, frames: &mut [Frame]) {
+        for frame in frames {
+            frame.planes.truncate(self.n_planes);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FrameLookbackRanges {
+    max_lookback: ColorVal,
+    alpha: (ColorVal, ColorVal),
+    src: Box<dyn ColorRanges>,
+}
+
+impl ColorRanges for FrameLookbackRanges {
+    fn num_planes(&self) -> usize {
+        LOOKBACK_PLANE + 1
+    }
+
+    fn min(&self, p: usize) -> ColorVal {
+        match p {
+            0..=2 => self.src.min(p),
+            3 => self.alpha.0,
+            _ => 0,
+        }
+    }
+
+    fn max(&self, p: usize) -> ColorVal {
+        match p {
+            0..=2 => self.src.max(p),
+            3 => self.alpha.1,
+            _ => self.max_lookback,
+        }
+    }
+
+    fn minmax(&self, p: usize, prev: &[ColorVal]) -> (ColorVal, ColorVal) {
+        if p < 3 {
+            self.src.minmax(p, prev)
+        } else {
+            (self.min(p), self.max(p))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use colors::StaticColorRanges;
+    use maniac::rac::Input24;
+
+    fn frames(n: usize) -> Vec<Frame> {
+        (0..n).map(|_| Frame::new(2, 2, None)).collect()
+    }
+
+    #[test]
+    fn adds_lookback_and_alpha_planes() {
+        let src = StaticColorRanges::new(vec![(0, 255); 3]);
+        let mut decoder = UniformSymbolDecoder::new(Input24::new(&[0; 64][..]).unwrap());
+        let transform = FrameLookback::read(&mut decoder, &src, &frames(4)).unwrap();
+        let ranges = transform.ranges(Box::new(src));
+
+        assert_eq!(ranges.num_planes(), 5);
+        assert_eq!((ranges.min(3), ranges.max(3)), (1, 1));
+        assert_eq!((ranges.min(LOOKBACK_PLANE), ranges.max(LOOKBACK_PLANE)), (0, 3));
+
+        let mut frames = frames(1);
+        frames[0].allocate_planes(5);
+        transform.undo(&mut frames);
+        assert_eq!(frames[0].planes.len(), 3);
+    }
+
+    #[test]
+    fn rejects_gray_source() {
+        let src = StaticColorRanges::new(vec![(0, 255)]);
+        let mut decoder = UniformSymbolDecoder::new(Input24::new(&[0; 64][..]).unwrap());
+        match FrameLookback::read(&mut decoder, &src, &frames(4)) {
+            Err(Error::Invalid(Transformation::FrameLookback)) => {}
+            other => panic!("expected a gray source to be rejected, got {:?}", other),
+        }
+    }
+}
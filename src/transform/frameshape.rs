@@ -0,0 +1,90 @@
+use std::io::Read;
+use colors::ColorRanges;
+use frame::Frame;
+use maniac::{rac, UniformSymbolDecoder};
+use maniac::symbol::SimpleSymbolDecoder;
+use super::{Error, Transform, Transformation};
+
+/// Only stores the part of every row that differs from the previous frame
+#[derive(Debug)]
+pub struct FrameShape {
+    /// `(begin, end)` of every row of all frames after the first, except duplicates
+    rows: Vec<(usize, usize)>,
+}
+
+impl FrameShape {
+    pub fn read<C: rac::Config, R: Read>(decoder: &mut UniformSymbolDecoder<C, R>, frames: &[Frame]) -> Result<Self, Error> {
+        let unique_frames = frames.iter().skip(1).filter(|frame| frame.seen_before.is_none()).count();
+        let (cols, n_rows) = match frames.first() {
+            Some(frame) => (frame.width as i32, unique_frames * frame.height),
+            None => (0, 0),
+        };
+
+        let mut coder = SimpleSymbolDecoder::default();
+        let rac = decoder.rac();
+
+        let mut begin = Vec::with_capacity(n_rows);
+        for _ in 0..n_rows {
+            begin.push(coder.read_int(rac, 0, cols)?);
+        }
+
+        let mut rows = Vec::with_capacity(n_rows);
+        for b in begin {
+            let e = cols - coder.read_int(rac, 0, cols - b)?;
+            if e <= 0 {
+                return Err(Error::Invalid(Transformation::FrameShape));
+            }
+            rows.push((b as usize, e as usize));
+        }
+
+        Ok(FrameShape {
+            rows: rows,
+        })
+    }
+}
+
+impl Transform for FrameShape {
+    fn kind(&self) -> Transformation {
+        Transformation::FrameShape
+    }
+
+    fn ranges(&self, src: Box<dyn ColorRanges>) -> Box<dyn ColorRanges> {
+        src
+    }
+
+    fn configure(&self, frames: &mut [Frame]) {
+        let mut rows = self.rows.iter();
+        for frame in frames.iter_mut().skip(1).filter(|frame| frame.seen_before.is_none()) {
+            for r in 0..frame.height {
+                if let Some(&(begin, end)) = rows.next() {
+                    frame.col_begin[r] = begin;
+                    frame.col_end[r] = end;
+                }
+            }
+        }
+    }
+
+    fn undo(&self, _frames: &mut [Frame]) {
+        // The columns outside of the shape are copied while decoding
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maniac::rac::Input24;
+
+    #[test]
+    fn skips_duplicate_frames() {
+        let mut frames: Vec<_> = (0..3).map(|_| Frame::new(4, 2, None)).collect();
+        frames[1].seen_before = Some(0);
+        // Only 0 bits: every row begins at the end
+        let mut decoder = UniformSymbolDecoder::new(Input24::new(&[0; 64][..]).unwrap());
+        let transform = FrameShape::read(&mut decoder, &frames).unwrap();
+        transform.configure(&mut frames);
+
+        assert_eq!(transform.rows.len(), 2);
+        assert_eq!((&frames[1].col_begin[..], &frames[1].col_end[..]), (&[0, 0][..], &[4, 4][..]));
+        assert_eq!((&frames[2].col_begin[..], &frames[2].col_end[..]), (&[4, 4][..], &[4, 4][..]));
+    }
+}
@@ -0,0 +1,144 @@
+use std::io::Read;
+use std::fmt::Debug;
+use colors::ColorRanges;
+use frame::Frame;
+use maniac::{rac, symbol, UniformSymbolDecoder};
+
+mod channelcompact;
+mod ycocg;
+mod permute;
+mod bounds;
+mod palette;
+mod colorbuckets;
+mod framedup;
+mod frameshape;
+mod framelookback;
+
+use self::channelcompact::ChannelCompact;
+use self::ycocg::YCoCg;
+use self::permute::PermutePlanes;
+use self::bounds::Bounds;
+use self::palette::{Palette, PaletteAlpha};
+use self::colorbuckets::ColorBuckets;
+use self::framedup::DuplicateFrame;
+use self::frameshape::FrameShape;
+use self::framelookback::FrameLookback;
+
+pub use self::framelookback::LOOKBACK_PLANE;
+
+const MAX_TRANSFORM: isize = 13;
+
+/// The transformations a FLIF image can be encoded with
+#[derive(Debug,Copy,Clone,PartialEq,Eq)]
+pub enum Transformation {
+    ChannelCompact,
+    YCoCg,
+    PermutePlanes,
+    Bounds,
+    PaletteAlpha,
+    Palette,
+    ColorBuckets,
+    DuplicateFrame,
+    FrameShape,
+    FrameLookback,
+}
+
+impl Transformation {
+    pub fn from_id(id: isize) -> Result<Self, Error> {
+        Ok(match id {
+            0 => Transformation::ChannelCompact,
+            1 => Transformation::YCoCg,
+            3 => Transformation::PermutePlanes,
+            4 => Transformation::Bounds,
+            5 => Transformation::PaletteAlpha,
+            6 => Transformation::Palette,
+            7 => Transformation::ColorBuckets,
+            10 => Transformation::DuplicateFrame,
+            11 => Transformation::FrameShape,
+            12 => Transformation::FrameLookback,
+            _ => return Err(Error::UnknownTransformation(id)),
+        })
+    }
+}
+
+/// A transformation whose parameters have been read from the stream
+pub trait Transform: Debug {
+    fn kind(&self) -> Transformation;
+
+    /// The ranges of the planes after this transformation has been applied
+    fn ranges(&self, src: Box<dyn ColorRanges>) -> Box<dyn ColorRanges>;
+
+    /// Passes information about the frames on to the pixel decoder
+    fn configure(&self, _frames: &mut [Frame]) {}
+
+    /// Undoes the transformation on the decoded frames
+    fn undo(&self, frames: &mut [Frame]);
+}
+
+/// What the transformations need to know from the image header
+#[derive(Debug,Copy,Clone)]
+pub struct Header {
+    /// Whether fully transparent pixels have undefined color values
+    pub alpha_zero: bool,
+}
+
+/// The transformations of an image
+pub struct Chain {
+    /// In the order they were applied by the encoder
+    pub transforms: Vec<Box<dyn Transform>>,
+    /// The ranges of the planes after all transformations
+    pub ranges: Box<dyn ColorRanges>,
+}
+
+/// Reads the transformations until the end marker and configures the `frames` for them.
+pub fn read_chain<C: rac::Config, R: Read>(decoder: &mut UniformSymbolDecoder<C, R>, mut ranges: Box<dyn ColorRanges>, header: &Header, frames: &mut [Frame]) -> Result<Chain, Error> {
+    let mut transforms = Vec::new();
+
+    while decoder.read_bool()? {
+        let id = decoder.read_int(0, MAX_TRANSFORM)?;
+        let kind = Transformation::from_id(id)?;
+        debug!("Transformation: {:?}", kind);
+
+        let transform = load(kind, decoder, &*ranges, header, frames)?;
+        ranges = transform.ranges(ranges);
+        transform.configure(frames);
+        transforms.push(transform);
+    }
+
+    Ok(Chain {
+        transforms: transforms,
+        ranges: ranges,
+    })
+}
+
+fn load<C: rac::Config, R: Read>(kind: Transformation, decoder: &mut UniformSymbolDecoder<C, R>, ranges: &dyn ColorRanges, header: &Header, frames: &[Frame]) -> Result<Box<dyn Transform>, Error> {
+    Ok(match kind {
+        Transformation::ChannelCompact => Box::new(ChannelCompact::read(decoder, ranges)?),
+        Transformation::YCoCg => Box::new(YCoCg::new(ranges)?),
+        Transformation::PermutePlanes => Box::new(PermutePlanes::read(decoder, ranges)?),
+        Transformation::Bounds => Box::new(Bounds::read(decoder, ranges)?),
+        Transformation::PaletteAlpha => Box::new(PaletteAlpha::read(decoder, ranges, header)?),
+        Transformation::Palette => Box::new(Palette::read(decoder, ranges)?),
+        Transformation::ColorBuckets => Box::new(ColorBuckets::read(decoder, ranges)?),
+        Transformation::DuplicateFrame => Box::new(DuplicateFrame::read(decoder, frames)?),
+        Transformation::FrameShape => Box::new(FrameShape::read(decoder, frames)?),
+        Transformation::FrameLookback => Box::new(FrameLookback::read(decoder, ranges, frames)?),
+    })
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        UnknownTransformation(id: isize) {
+            description("Unknown transformation")
+            display("Unknown transformation `{}`", id)
+        }
+        Invalid(kind: Transformation) {
+            description("Transformation cannot be applied to these ranges")
+            display("Transformation `{:?}` cannot be applied to these ranges", kind)
+        }
+        Symbol(err: symbol::Error) {
+            from()
+        }
+    }
+}
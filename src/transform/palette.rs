@@ -0,0 +1,357 @@
+use std::io::Read;
+use colors::ColorRanges;
+use frame::{ColorVal, Frame};
+use maniac::{rac, UniformSymbolDecoder};
+use maniac::symbol::SimpleSymbolDecoder;
+use super::{Error, Header, Transform, Transformation};
+
+const MAX_PALETTE_SIZE: i32 = 30_000;
+
+/// Replaces the colors by indices into a palette (stored in plane 1)
+#[derive(Debug)]
+pub struct Palette {
+    /// `(Y, Co, Cg)` of every color
+    colors: Vec<(ColorVal, ColorVal, ColorVal)>,
+}
+
+impl Palette {
+    pub fn read<C: rac::Config, R: Read>(decoder: &mut UniformSymbolDecoder<C, R>, ranges: &dyn ColorRanges) -> Result<Self, Error> {
+        if ranges.num_planes() < 3 {
+            return Err(Error::Invalid(Transformation::Palette));
+        }
+
+        let mut coder = SimpleSymbolDecoder::default();
+        let mut coder_y = SimpleSymbolDecoder::default();
+        let mut coder_i = SimpleSymbolDecoder::default();
+        let mut coder_q = SimpleSymbolDecoder::default();
+        let rac = decoder.rac();
+
+        let size = coder.read_int(rac, 1, MAX_PALETTE_SIZE)?;
+        let sorted = coder.read_bool(rac)?;
+        debug!("palette with {} colors (sorted: {})", size, sorted);
+
+        let mut colors: Vec<(ColorVal, ColorVal, ColorVal)> = Vec::with_capacity(size as usize);
+        for _ in 0..size {
+            let prev = colors.last().cloned();
+
+            let (mut min, max) = ranges.minmax(0, &[]);
+            if let (true, Some(prev)) = (sorted, prev) {
+                // Sorted palettes never decrease in luma
+                min = prev.0;
+            }
+            let y = coder_y.read_int(rac, min, max)?;
+
+            let (mut min, max) = ranges.minmax(1, &[y]);
+            match (sorted, prev) {
+                (true, Some(prev)) if prev.0 == y => min = prev.1,
+                _ => {}
+            }
+            let i = coder_i.read_int(rac, min, max)?;
+
+            let (min, max) = ranges.minmax(2, &[y, i]);
+            let q = coder_q.read_int(rac, min, max)?;
+
+            colors.push((y, i, q));
+        }
+
+        Ok(Palette {
+            colors: colors,
+        })
+    }
+}
+
+impl Transform for Palette {
+    fn kind(&self) -> Transformation {
+        Transformation::Palette
+    }
+
+    fn ranges(&self, src: Box<dyn ColorRanges>) -> Box<dyn ColorRanges> {
+        Box::new(PaletteRanges {
+            n_colors: self.colors.len() as ColorVal,
+            alpha: false,
+            src: src,
+        })
+    }
+
+    fn undo(&self, frames: &mut [Frame]) {
+        for frame in frames {
+            for i in 0..frame.width * frame.height {
+                let index = frame.planes[1][i];
+                let (y, co, cg) = lookup(&self.colors, index);
+                frame.planes[0][i] = y;
+                frame.planes[1][i] = co;
+                frame.planes[2][i] = cg;
+            }
+        }
+    }
+}
+
+/// Like `Palette`, but the alpha value is part of the palette entries
+#[derive(Debug)]
+pub struct PaletteAlpha {
+    /// `(A, Y, Co, Cg)` of every color
+    colors: Vec<(ColorVal, ColorVal, ColorVal, ColorVal)>,
+}
+
+impl PaletteAlpha {
+    pub fn read<C: rac::Config, R: Read>(decoder: &mut UniformSymbolDecoder<C, R>, ranges: &dyn ColorRanges, header: &Header) -> Result<Self, Error> {
+        if ranges.num_planes() < 4 {
+            return Err(Error::Invalid(Transformation::PaletteAlpha));
+        }
+
+        let mut coder = SimpleSymbolDecoder::default();
+        let mut coder_y = SimpleSymbolDecoder::default();
+        let mut coder_i = SimpleSymbolDecoder::default();
+        let mut coder_q = SimpleSymbolDecoder::default();
+        let mut coder_a = SimpleSymbolDecoder::default();
+        let rac = decoder.rac();
+
+        let size = coder.read_int(rac, 1, MAX_PALETTE_SIZE)?;
+        let sorted = coder.read_bool(rac)?;
+        debug!("palette with {} colors and alpha (sorted: {})", size, sorted);
+
+        let mut colors: Vec<(ColorVal, ColorVal, ColorVal, ColorVal)> = Vec::with_capacity(size as usize);
+        for _ in 0..size {
+            let prev = colors.last().cloned();
+
+            let (mut min, max) = ranges.minmax(3, &[]);
+            if let (true, Some(prev)) = (sorted, prev) {
+                // Sorted palettes never decrease in alpha
+                min = prev.0;
+            }
+            let a = coder_a.read_int(rac, min, max)?;
+
+            if header.alpha_zero && a == 0 {
+                colors.push((0, 0, 0, 0));
+                continue;
+            }
+
+            let (mut min, max) = ranges.minmax(0, &[]);
+            match (sorted, prev) {
+                (true, Some(prev)) if prev.0 == a => min = prev.1,
+                _ => {}
+            }
+            let y = coder_y.read_int(rac, min, max)?;
+
+            let (min, max) = ranges.minmax(1, &[y]);
+            let i = coder_i.read_int(rac, min, max)?;
+
+            let (min, max) = ranges.minmax(2, &[y, i]);
+            let q = coder_q.read_int(rac, min, max)?;
+
+            colors.push((a, y, i, q));
+        }
+
+        Ok(PaletteAlpha {
+            colors: colors,
+        })
+    }
+}
+
+impl Transform for PaletteAlpha {
+    fn kind(&self) -> Transformation {
+        Transformation::PaletteAlpha
+    }
+
+    fn ranges(&self, src: Box<dyn ColorRanges>) -> Box<dyn ColorRanges> {
+        Box::new(PaletteRanges {
+            n_colors: self.colors.len() as ColorVal,
+            alpha: true,
+            src: src,
+        })
+    }
+
+    fn undo(&self, frames: &mut [Frame]) {
+        for frame in frames {
+            for i in 0..frame.width * frame.height {
+                let index = frame.planes[1][i];
+                let (a, y, co, cg) = lookup(&self.colors, index);
+                frame.planes[0][i] = y;
+                frame.planes[1][i] = co;
+                frame.planes[2][i] = cg;
+                frame.planes[3][i] = a;
+            }
+        }
+    }
+}
+
+/// Corrupt indices fall back to the first color
+fn lookup<T: Copy>(colors: &[T], index: ColorVal) -> T {
+    if index < 0 || index as usize >= colors.len() {
+        return colors[0];
+    }
+
+    colors[index as usize]
+}
+
+#[derive(Debug)]
+struct PaletteRanges {
+    n_colors: ColorVal,
+    /// Whether the alpha plane is part of the palette
+    alpha: bool,
+    src: Box<dyn ColorRanges>,
+}
+
+impl ColorRanges for PaletteRanges {
+    fn num_planes(&self) -> usize {
+        self.src.num_planes()
+    }
+
+    fn min(&self, p: usize) -> ColorVal {
+        match p {
+            0..=2 => 0,
+            3 if self.alpha => 1,
+            _ => self.src.min(p),
+        }
+    }
+
+    fn max(&self, p: usize) -> ColorVal {
+        match p {
+            0 | 2 => 0,
+            1 => self.n_colors - 1,
+            3 if self.alpha => 1,
+            _ => self.src.max(p),
+        }
+    }
+
+    fn is_static(&self) -> bool {
+        self.src.is_static()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use colors::StaticColorRanges;
+    use maniac::encoder::{Output, SimpleSymbolEncoder};
+    use maniac::rac::Input24;
+    use transform::YCoCg;
+
+    fn ycocg(n_planes: usize) -> Box<dyn ColorRanges> {
+        let src = StaticColorRanges::new(vec![(0, 255); n_planes]);
+        YCoCg::new(&src).unwrap().ranges(Box::new(src))
+    }
+
+    fn decoder(data: &[u8]) -> UniformSymbolDecoder<rac::Config24, &[u8]> {
+        UniformSymbolDecoder::new(Input24::new(data).unwrap())
+    }
+
+    /// Encodes `colors` as `Palette::read` expects them
+    fn encode(ranges: &dyn ColorRanges, colors: &[(ColorVal, ColorVal, ColorVal)], sorted: bool) -> Vec<u8> {
+        let mut output = Output::new();
+        let (mut coder, mut coder_y, mut coder_i, mut coder_q) =
+            (SimpleSymbolEncoder::new(), SimpleSymbolEncoder::new(), SimpleSymbolEncoder::new(), SimpleSymbolEncoder::new());
+
+        coder.write_int(&mut output, 1, MAX_PALETTE_SIZE, colors.len() as ColorVal);
+        coder.write_bool(&mut output, sorted);
+        let mut prev: Option<(ColorVal, ColorVal, ColorVal)> = None;
+        for &(y, i, q) in colors {
+            let (min, max) = ranges.minmax(0, &[]);
+            let min = match prev { Some(prev) if sorted => prev.0, _ => min };
+            coder_y.write_int(&mut output, min, max, y);
+
+            let (min, max) = ranges.minmax(1, &[y]);
+            let min = match prev { Some(prev) if sorted && prev.0 == y => prev.1, _ => min };
+            coder_i.write_int(&mut output, min, max, i);
+
+            let (min, max) = ranges.minmax(2, &[y, i]);
+            coder_q.write_int(&mut output, min, max, q);
+            prev = Some((y, i, q));
+        }
+
+        output.finish()
+    }
+
+    #[test]
+    fn reads_unsorted_colors() {
+        let ranges = ycocg(3);
+        let colors = vec![(100, -20, 30), (50, 10, -5), (200, 0, 0), (3, 0, 0)];
+        let data = encode(&*ranges, &colors, false);
+
+        let transform = Palette::read(&mut decoder(&data), &*ranges).unwrap();
+        assert_eq!(transform.colors, colors);
+    }
+
+    #[test]
+    fn reads_sorted_colors_relative_to_the_previous_one() {
+        let ranges = ycocg(3);
+        let colors = vec![(10, 0, 0), (10, 5, -3), (10, 5, 7), (60, -10, 2)];
+        let data = encode(&*ranges, &colors, true);
+
+        let transform = Palette::read(&mut decoder(&data), &*ranges).unwrap();
+        assert_eq!(transform.colors, colors);
+    }
+
+    /// Encodes `colors` as `PaletteAlpha::read` expects them
+    fn encode_alpha(ranges: &dyn ColorRanges, colors: &[(ColorVal, ColorVal, ColorVal, ColorVal)], alpha_zero: bool) -> Vec<u8> {
+        let mut output = Output::new();
+        let mut coder = SimpleSymbolEncoder::new();
+        let mut coders: Vec<_> = (0..4).map(|_| SimpleSymbolEncoder::new()).collect();
+
+        coder.write_int(&mut output, 1, MAX_PALETTE_SIZE, colors.len() as ColorVal);
+        coder.write_bool(&mut output, false);
+        for &(a, y, i, q) in colors {
+            let (min, max) = ranges.minmax(3, &[]);
+            coders[3].write_int(&mut output, min, max, a);
+            if alpha_zero && a == 0 {
+                continue;
+            }
+
+            let (min, max) = ranges.minmax(0, &[]);
+            coders[0].write_int(&mut output, min, max, y);
+            let (min, max) = ranges.minmax(1, &[y]);
+            coders[1].write_int(&mut output, min, max, i);
+            let (min, max) = ranges.minmax(2, &[y, i]);
+            coders[2].write_int(&mut output, min, max, q);
+        }
+
+        output.finish()
+    }
+
+    #[test]
+    fn invisible_colors_only_store_alpha() {
+        let ranges = ycocg(4);
+        let colors = vec![(255, 100, -20, 30), (0, 50, 10, -5), (128, 200, 0, 0)];
+
+        let data = encode_alpha(&*ranges, &colors, true);
+        let header = Header { alpha_zero: true };
+        let transform = PaletteAlpha::read(&mut decoder(&data), &*ranges, &header).unwrap();
+        assert_eq!(transform.colors, vec![(255, 100, -20, 30), (0, 0, 0, 0), (128, 200, 0, 0)]);
+
+        let data = encode_alpha(&*ranges, &colors, false);
+        let header = Header { alpha_zero: false };
+        let transform = PaletteAlpha::read(&mut decoder(&data), &*ranges, &header).unwrap();
+        assert_eq!(transform.colors, colors);
+    }
+
+    #[test]
+    fn undo_looks_up_the_indices() {
+        let transform = PaletteAlpha {
+            colors: vec![(255, 1, 2, 3), (128, 4, 5, 6)],
+        };
+        let mut frames = vec![Frame::new(3, 1, None)];
+        frames[0].allocate_planes(4);
+        // An index beyond the palette falls back to the first color
+        frames[0].planes[1] = vec![1, 0, 7];
+        transform.undo(&mut frames);
+
+        assert_eq!(frames[0].planes, vec![vec![4, 1, 1], vec![5, 2, 2], vec![6, 3, 3], vec![128, 255, 255]]);
+    }
+
+    #[test]
+    fn ranges_leave_only_the_index() {
+        let src = StaticColorRanges::new(vec![(0, 255); 4]);
+        let transform = Palette {
+            colors: vec![(0, 0, 0); 5],
+        };
+        let ranges = transform.ranges(Box::new(src.clone()));
+        let planes: Vec<_> = (0..4).map(|p| (ranges.min(p), ranges.max(p))).collect();
+        assert_eq!(planes, vec![(0, 0), (0, 4), (0, 0), (0, 255)]);
+
+        let transform = PaletteAlpha {
+            colors: vec![(0, 0, 0, 0); 5],
+        };
+        let ranges = transform.ranges(Box::new(src));
+        assert_eq!((ranges.min(3), ranges.max(3)), (1, 1));
+    }
+}
@@ -0,0 +1,191 @@
+use std::io::Read;
+use colors::{self, ColorRanges};
+use frame::{ColorVal, Frame};
+use maniac::{rac, UniformSymbolDecoder};
+use maniac::symbol::SimpleSymbolDecoder;
+use super::{Error, Transform, Transformation};
+
+/// Reorders the planes and optionally subtracts the first plane from the chroma planes
+#[derive(Debug)]
+pub struct PermutePlanes {
+    /// Plane `p` of the transformed image is plane `permutation[p]` of the original
+    permutation: Vec<usize>,
+    subtract: bool,
+    /// The ranges of the original planes
+    src: Vec<(ColorVal, ColorVal)>,
+}
+
+impl PermutePlanes {
+    pub fn read<C: rac::Config, R: Read>(decoder: &mut UniformSymbolDecoder<C, R>, ranges: &dyn ColorRanges) -> Result<Self, Error> {
+        let n_planes = ranges.num_planes();
+        if n_planes < 3 {
+            return Err(Error::Invalid(Transformation::PermutePlanes));
+        }
+
+        let mut coder = SimpleSymbolDecoder::default();
+        let rac = decoder.rac();
+
+        let subtract = coder.read_bool(rac)?;
+        let mut permutation = Vec::with_capacity(n_planes);
+        let mut used = vec![false; n_planes];
+        for _ in 0..n_planes {
+            let p = coder.read_int(rac, 0, n_planes as ColorVal - 1)? as usize;
+            permutation.push(p);
+            used[p] = true;
+        }
+
+        if used.contains(&false) {
+            return Err(Error::Invalid(Transformation::PermutePlanes));
+        }
+        debug!("permutation: {:?} (subtract: {})", permutation, subtract);
+
+        Ok(PermutePlanes {
+            permutation: permutation,
+            subtract: subtract,
+            src: (0..n_planes).map(|p| (ranges.min(p), ranges.max(p))).collect(),
+        })
+    }
+}
+
+impl Transform for PermutePlanes {
+    fn kind(&self) -> Transformation {
+        Transformation::PermutePlanes
+    }
+
+    fn ranges(&self, src: Box<dyn ColorRanges>) -> Box<dyn ColorRanges> {
+        Box::new(PermuteRanges {
+            permutation: self.permutation.clone(),
+            subtract: self.subtract,
+            src: src,
+        })
+    }
+
+    fn undo(&self, frames: &mut [Frame]) {
+        let mut pixel = vec![0; self.permutation.len()];
+        for frame in frames {
+            for i in 0..frame.width * frame.height {
+                for (p, value) in pixel.iter_mut().enumerate() {
+                    *value = frame.planes[p][i];
+                }
+
+                for (p, &target) in self.permutation.iter().enumerate() {
+                    let mut value = pixel[p];
+                    if self.subtract && (p == 1 || p == 2) {
+                        let (min, max) = self.src[target];
+                        value = colors::clamp(value + pixel[0], min, max);
+                    }
+                    frame.planes[target][i] = value;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PermuteRanges {
+    permutation: Vec<usize>,
+    subtract: bool,
+    src: Box<dyn ColorRanges>,
+}
+
+impl PermuteRanges {
+    fn is_subtracted(&self, p: usize) -> bool {
+        self.subtract && (p == 1 || p == 2)
+    }
+}
+
+impl ColorRanges for PermuteRanges {
+    fn num_planes(&self) -> usize {
+        self.src.num_planes()
+    }
+
+    fn min(&self, p: usize) -> ColorVal {
+        let min = self.src.min(self.permutation[p]);
+        if self.is_subtracted(p) {
+            min - self.src.max(self.permutation[0])
+        } else {
+            min
+        }
+    }
+
+    fn max(&self, p: usize) -> ColorVal {
+        let max = self.src.max(self.permutation[p]);
+        if self.is_subtracted(p) {
+            max - self.src.min(self.permutation[0])
+        } else {
+            max
+        }
+    }
+
+    fn minmax(&self, p: usize, prev: &[ColorVal]) -> (ColorVal, ColorVal) {
+        if self.is_subtracted(p) {
+            let target = self.permutation[p];
+            (self.src.min(target) - prev[0], self.src.max(target) - prev[0])
+        } else {
+            (self.min(p), self.max(p))
+        }
+    }
+
+    fn is_static(&self) -> bool {
+        !self.subtract && self.src.is_static()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use colors::StaticColorRanges;
+    use maniac::encoder::{Output, SimpleSymbolEncoder};
+    use maniac::rac::Input24;
+
+    fn read(src: &dyn ColorRanges, subtract: bool, permutation: &[ColorVal]) -> Result<PermutePlanes, Error> {
+        let mut output = Output::new();
+        let mut coder = SimpleSymbolEncoder::new();
+        coder.write_bool(&mut output, subtract);
+        for &p in permutation {
+            coder.write_int(&mut output, 0, src.num_planes() as ColorVal - 1, p);
+        }
+        let data = output.finish();
+
+        let mut decoder = UniformSymbolDecoder::new(Input24::new(&data[..]).unwrap());
+        PermutePlanes::read(&mut decoder, src)
+    }
+
+    #[test]
+    fn rejects_planes_used_twice() {
+        let src = StaticColorRanges::new(vec![(0, 255); 3]);
+        match read(&src, false, &[0, 2, 2]) {
+            Err(Error::Invalid(Transformation::PermutePlanes)) => {}
+            other => panic!("expected the permutation to be rejected, got {:?}", other),
+        }
+        assert_eq!(read(&src, false, &[1, 2, 0]).unwrap().permutation, vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn subtracted_ranges_depend_on_the_first_plane() {
+        let src = StaticColorRanges::new(vec![(0, 255), (10, 20), (0, 100)]);
+        let transform = read(&src, true, &[1, 0, 2]).unwrap();
+        let ranges = transform.ranges(Box::new(src));
+
+        assert_eq!((ranges.min(0), ranges.max(0)), (10, 20));
+        assert_eq!((ranges.min(1), ranges.max(1)), (-20, 245));
+        assert_eq!((ranges.min(2), ranges.max(2)), (-20, 90));
+        assert_eq!(ranges.minmax(2, &[15]), (-15, 85));
+        assert_eq!(ranges.minmax(0, &[]), (10, 20));
+        assert!(!ranges.is_static());
+    }
+
+    #[test]
+    fn undo_adds_the_first_plane_back_and_clamps() {
+        let src = StaticColorRanges::new(vec![(0, 255), (10, 20), (0, 100)]);
+        let transform = read(&src, true, &[1, 0, 2]).unwrap();
+
+        let mut frames = vec![Frame::new(2, 1, None)];
+        frames[0].allocate_planes(3);
+        frames[0].planes = vec![vec![15, 20], vec![100, 240], vec![-5, 90]];
+        transform.undo(&mut frames);
+
+        // The second pixel is out of range after adding 20 and gets clamped
+        assert_eq!(frames[0].planes, vec![vec![115, 255], vec![15, 20], vec![10, 100]]);
+    }
+}
@@ -0,0 +1,187 @@
+use std::cmp;
+use colors::{self, ColorRanges};
+use frame::{ColorVal, Frame};
+use super::{Error, Transform, Transformation};
+
+/// Lossless conversion from RGB to luma and two chroma planes
+#[derive(Debug)]
+pub struct YCoCg {
+    /// A quarter of the largest RGB value, rounded up
+    par: ColorVal,
+    /// The largest values of the original R, G and B planes
+    max: [ColorVal; 3],
+}
+
+impl YCoCg {
+    pub fn new(ranges: &dyn ColorRanges) -> Result<Self, Error> {
+        if ranges.num_planes() < 3 {
+            return Err(Error::Invalid(Transformation::YCoCg));
+        }
+
+        for p in 0..3 {
+            if ranges.min(p) < 0 || ranges.min(p) == ranges.max(p) {
+                return Err(Error::Invalid(Transformation::YCoCg));
+            }
+        }
+
+        let max = [ranges.max(0), ranges.max(1), ranges.max(2)];
+        let par = cmp::max(cmp::max(max[0], max[1]), max[2]) / 4 + 1;
+
+        Ok(YCoCg {
+            par: par,
+            max: max,
+        })
+    }
+}
+
+impl Transform for YCoCg {
+    fn kind(&self) -> Transformation {
+        Transformation::YCoCg
+    }
+
+    fn ranges(&self, src: Box<dyn ColorRanges>) -> Box<dyn ColorRanges> {
+        Box::new(YCoCgRanges {
+            par: self.par,
+            src: src,
+        })
+    }
+
+    fn undo(&self, frames: &mut [Frame]) {
+        for frame in frames {
+            let (rgb, _) = frame.planes.split_at_mut(3);
+            let (ys, rgb) = rgb.split_first_mut().unwrap();
+            let (cos, cgs) = rgb.split_first_mut().unwrap();
+            let cgs = &mut cgs[0];
+
+            for i in 0..ys.len() {
+                let (y, co, cg) = (ys[i], cos[i], cgs[i]);
+                let g = y - ((-cg) >> 1);
+                let b = y + ((1 - cg) >> 1) - (co >> 1);
+                let r = co + b;
+
+                ys[i] = colors::clamp(r, 0, self.max[0]);
+                cos[i] = colors::clamp(g, 0, self.max[1]);
+                cgs[i] = colors::clamp(b, 0, self.max[2]);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct YCoCgRanges {
+    par: ColorVal,
+    src: Box<dyn ColorRanges>,
+}
+
+impl YCoCgRanges {
+    fn min_co(&self, y: ColorVal) -> ColorVal {
+        let par = self.par;
+        if y < par - 1 {
+            -3 - 4 * y
+        } else if y >= 3 * par {
+            4 * (y - 4 * par + 1)
+        } else {
+            -4 * par + 1
+        }
+    }
+
+    fn max_co(&self, y: ColorVal) -> ColorVal {
+        let par = self.par;
+        if y < par - 1 {
+            3 + 4 * y
+        } else if y >= 3 * par {
+            4 * (4 * par - 1 - y)
+        } else {
+            4 * par - 1
+        }
+    }
+
+    fn min_cg(&self, y: ColorVal, co: ColorVal) -> ColorVal {
+        let par = self.par;
+        let co = co.abs();
+        if y < par - 1 {
+            -(2 * y + 1)
+        } else if y >= 3 * par {
+            -(2 * (4 * par - 1 - y) - ((1 + co) / 2) * 2)
+        } else {
+            -cmp::min(2 * par - 1 + (y - par + 1) * 2, 2 * par + (3 * par - 1 - y) * 2 - ((1 + co) / 2) * 2)
+        }
+    }
+
+    fn max_cg(&self, y: ColorVal, co: ColorVal) -> ColorVal {
+        let par = self.par;
+        let co = co.abs();
+        if y < par - 1 {
+            1 + 2 * y - 2 * (co / 2)
+        } else if y >= 3 * par {
+            2 * (4 * par - 1 - y)
+        } else {
+            -cmp::max(-4 * par + (1 + y - 2 * par) * 2, -2 * par - (y - par) * 2 - 1 + (co / 2) * 2)
+        }
+    }
+}
+
+impl ColorRanges for YCoCgRanges {
+    fn num_planes(&self) -> usize {
+        self.src.num_planes()
+    }
+
+    fn min(&self, p: usize) -> ColorVal {
+        match p {
+            0 => 0,
+            1 | 2 => -4 * self.par + 1,
+            _ => self.src.min(p),
+        }
+    }
+
+    fn max(&self, p: usize) -> ColorVal {
+        match p {
+            0..=2 => 4 * self.par - 1,
+            _ => self.src.max(p),
+        }
+    }
+
+    fn minmax(&self, p: usize, prev: &[ColorVal]) -> (ColorVal, ColorVal) {
+        match p {
+            1 => (self.min_co(prev[0]), self.max_co(prev[0])),
+            2 => (self.min_cg(prev[0], prev[1]), self.max_cg(prev[0], prev[1])),
+            _ => (self.min(p), self.max(p)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use colors::StaticColorRanges;
+
+    #[test]
+    fn undo_restores_rgb_within_ranges() {
+        let max = 15;
+        let src = StaticColorRanges::new(vec![(0, max); 3]);
+        let transform = YCoCg::new(&src).unwrap();
+        let ranges = transform.ranges(Box::new(src));
+
+        let mut frame = Frame::new(1, 1, None);
+        frame.allocate_planes(3);
+        for r in 0..max + 1 {
+            for g in 0..max + 1 {
+                for b in 0..max + 1 {
+                    let co = r - b;
+                    let tmp = b + (co >> 1);
+                    let cg = g - tmp;
+                    let y = tmp + (cg >> 1);
+
+                    let (min, max) = ranges.minmax(1, &[y]);
+                    assert!(min <= co && co <= max);
+                    let (min, max) = ranges.minmax(2, &[y, co]);
+                    assert!(min <= cg && cg <= max);
+
+                    frame.planes = vec![vec![y], vec![co], vec![cg]];
+                    transform.undo(::std::slice::from_mut(&mut frame));
+                    assert_eq!(frame.planes, vec![vec![r], vec![g], vec![b]]);
+                }
+            }
+        }
+    }
+}
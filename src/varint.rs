@@ -77,7 +77,7 @@ mod test {
     fn edge() {
         assert_eq!(
             read_varint!(0x81, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F).unwrap(),
-            ::std::u64::MAX);
+            u64::MAX);
     }
 
     #[test]
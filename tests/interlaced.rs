@@ -0,0 +1,186 @@
+extern crate flif;
+extern crate flate2;
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Read;
+use std::rc::Rc;
+use flate2::read::ZlibDecoder;
+
+/// Decodes a non-interlaced 8 bit RGBA PNG
+fn read_png(path: &str) -> (usize, usize, Vec<u8>) {
+    let mut data = Vec::new();
+    File::open(path).unwrap().read_to_end(&mut data).unwrap();
+
+    let be32 = |bytes: &[u8]| (bytes[0] as usize) << 24 | (bytes[1] as usize) << 16 | (bytes[2] as usize) << 8 | bytes[3] as usize;
+    let (mut width, mut height) = (0, 0);
+    let mut compressed = Vec::new();
+    let mut pos = 8;
+    while pos < data.len() {
+        let len = be32(&data[pos..]);
+        let body = &data[pos + 8..pos + 8 + len];
+        match &data[pos + 4..pos + 8] {
+            b"IHDR" => {
+                width = be32(body);
+                height = be32(&body[4..]);
+                assert_eq!(&body[8..13], &[8, 6, 0, 0, 0]);
+            }
+            b"IDAT" => compressed.extend_from_slice(body),
+            _ => {}
+        }
+        pos += len + 12;
+    }
+
+    let mut filtered = Vec::new();
+    ZlibDecoder::new(&compressed[..]).read_to_end(&mut filtered).unwrap();
+
+    let stride = width * 4;
+    let mut pixels = vec![0u8; stride * height];
+    for r in 0..height {
+        let filter = filtered[r * (stride + 1)];
+        let line = &filtered[r * (stride + 1) + 1..(r + 1) * (stride + 1)];
+        for i in 0..stride {
+            let left = if i >= 4 { pixels[r * stride + i - 4] as i32 } else { 0 };
+            let up = if r > 0 { pixels[(r - 1) * stride + i] as i32 } else { 0 };
+            let up_left = if r > 0 && i >= 4 { pixels[(r - 1) * stride + i - 4] as i32 } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => (left + up) / 2,
+                4 => {
+                    let p = left + up - up_left;
+                    let (pa, pb, pc) = ((p - left).abs(), (p - up).abs(), (p - up_left).abs());
+                    if pa <= pb && pa <= pc { left } else if pb <= pc { up } else { up_left }
+                }
+                _ => panic!("unknown PNG filter {}", filter),
+            };
+            pixels[r * stride + i] = (line[i] as i32 + predicted) as u8;
+        }
+    }
+
+    (width, height, pixels)
+}
+
+fn decode(path: &str) -> Vec<flif::Image> {
+    let builder = flif::dec::decode(File::open(path).unwrap()).unwrap();
+    flif::dec::decode_image(builder, Default::default()).unwrap()
+}
+
+#[test]
+fn rust_matches_png() {
+    let images = decode("tests/fixtures/rust.flif");
+    let (width, height, expected) = read_png("tests/fixtures/rust.png");
+
+    assert_eq!(images.len(), 1);
+    assert_eq!((images[0].width(), images[0].height()), (width as u64, height as u64));
+    assert!(images[0].to_u8().as_slice() == &expected[..]);
+}
+
+#[test]
+fn decodes_webp_lossless() {
+    let images = decode("tests/fixtures/5_webp_ll.flif");
+    assert_eq!(images.len(), 1);
+    assert_eq!((images[0].width(), images[0].height()), (300, 300));
+}
+
+#[test]
+fn decodes_spinfox() {
+    let builder = flif::dec::decode(File::open("tests/fixtures/spinfox.flif").unwrap()).unwrap();
+    let transformations = builder.info().transformations();
+    assert!(transformations.contains(&flif::dec::Transformation::FrameShape));
+    assert!(transformations.contains(&flif::dec::Transformation::FrameLookback));
+
+    let images = flif::dec::decode_image(builder, Default::default()).unwrap();
+    assert_eq!(images.len(), 25);
+    assert_eq!((images[0].width(), images[0].height()), (148, 148));
+
+    // A spinning logo on a transparent background
+    let alpha = |image: &flif::Image, x: usize, y: usize| image.to_u8().as_slice()[(y * 148 + x) * 4 + 3];
+    for image in &images {
+        assert_eq!((alpha(image, 0, 0), alpha(image, 147, 147)), (0, 0));
+        assert_eq!(alpha(image, 74, 74), 255);
+    }
+    for pair in images.windows(2) {
+        assert!(pair[0].to_u8().as_slice() != pair[1].to_u8().as_slice());
+    }
+}
+
+#[test]
+fn scaled_down_images_keep_every_other_pixel() {
+    let (width, height, expected) = read_png("tests/fixtures/rust.png");
+    let options = flif::dec::DecoderOptions { scale_down: flif::dec::ScaleDownFactor::By2, ..Default::default() };
+    let builder = flif::dec::decode(File::open("tests/fixtures/rust.flif").unwrap()).unwrap();
+    let images = flif::dec::decode_image(builder, options).unwrap();
+
+    let (scaled_w, scaled_h) = ((width - 1) / 2 + 1, (height - 1) / 2 + 1);
+    assert_eq!((images[0].width(), images[0].height()), (scaled_w as u64, scaled_h as u64));
+
+    // Decoding stops at the zoomlevel that contains exactly these pixels
+    let mut subsampled = Vec::new();
+    for r in (0..height).step_by(2) {
+        for c in (0..width).step_by(2) {
+            subsampled.extend_from_slice(&expected[(r * width + c) * 4..(r * width + c + 1) * 4]);
+        }
+    }
+    assert!(images[0].to_u8().as_slice() == &subsampled[..]);
+}
+
+#[test]
+fn resize_dimensions_pick_the_scale() {
+    let options = flif::dec::DecoderOptions { resize_dimensions: Some((100, 100)), ..Default::default() };
+    let builder = flif::dec::decode(File::open("tests/fixtures/5_webp_ll.flif").unwrap()).unwrap();
+    let images = flif::dec::decode_image(builder, options).unwrap();
+
+    // 300x300 only fits into 100x100 at 1:4
+    assert_eq!((images[0].width(), images[0].height()), (75, 75));
+}
+
+#[test]
+fn scaling_down_needs_the_full_resolution_buffer() {
+    // 4 planes of 300x300 samples, 4 bytes each
+    let options = flif::dec::DecoderOptions {
+        scale_down: flif::dec::ScaleDownFactor::By8,
+        max_image_buffer_size: 4 * 300 * 300 * 4 - 1,
+        ..Default::default()
+    };
+    let builder = flif::dec::decode(File::open("tests/fixtures/5_webp_ll.flif").unwrap()).unwrap();
+    match flif::dec::decode_image(builder, options) {
+        Err(flif::dec::Error::BufferSizeExceedsLimit) => {}
+        other => panic!("expected the buffer to exceed the limit, got {:?}", other.err()),
+    }
+}
+
+/// The zoomlevel and the dimensions of every preview
+fn previews(path: &str, options: flif::dec::DecoderOptions) -> Vec<(usize, u64, u64)> {
+    let previews = Rc::new(RefCell::new(Vec::new()));
+    let seen = previews.clone();
+    let builder = flif::dec::decode(File::open(path).unwrap()).unwrap()
+        .on_preview(move |zoomlevel, images| {
+            seen.borrow_mut().push((zoomlevel, images[0].width(), images[0].height()));
+        });
+    flif::dec::decode_image(builder, options).unwrap();
+
+    let previews = previews.borrow().clone();
+    previews
+}
+
+#[test]
+fn previews_get_more_detailed() {
+    let previews = previews("tests/fixtures/rust.flif", Default::default());
+    let (width, height, _) = read_png("tests/fixtures/rust.png");
+
+    // 250x250 starts at zoomlevel 16, the full image at zoomlevel 0 is not a preview
+    let zoomlevels: Vec<usize> = previews.iter().map(|preview| preview.0).collect();
+    assert_eq!(zoomlevels, (1..17).rev().collect::<Vec<_>>());
+    assert!(previews.iter().all(|&(_, w, h)| (w, h) == (width as u64, height as u64)));
+}
+
+#[test]
+fn previews_of_scaled_down_images_are_scaled_down() {
+    let options = flif::dec::DecoderOptions { scale_down: flif::dec::ScaleDownFactor::By4, ..Default::default() };
+    let previews = previews("tests/fixtures/5_webp_ll.flif", options);
+
+    assert_eq!(previews.last().map(|preview| preview.0), Some(5));
+    assert!(previews.iter().all(|&(_, w, h)| (w, h) == (75, 75)));
+}
@@ -0,0 +1,88 @@
+extern crate flif;
+
+use std::fs::File;
+use std::io::Read;
+
+/// A 4x3 gray image whose header ends with `first` and filler after it
+fn gray(first: u8) -> Vec<u8> {
+    let mut data = b"FLIF\x31\x31\x03\x02\x00".to_vec();
+    data.push(first);
+    data.extend_from_slice(&[0xFF; 64]);
+    data
+}
+
+#[test]
+fn xmp_matches_the_embedded_file() {
+    let file = File::open("tests/fixtures/rust_fake_metadata.flif").unwrap();
+    let builder = flif::dec::decode(file).unwrap();
+    let info = builder.info();
+
+    let mut expected = Vec::new();
+    File::open("tests/fixtures/fake_metadata.xmp").unwrap().read_to_end(&mut expected).unwrap();
+
+    assert_eq!(info.xmp(), Some(&expected[..]));
+    assert_eq!(info.icc_profile(), None);
+    assert_eq!(info.exif(), None);
+}
+
+#[test]
+fn images_without_metadata() {
+    let file = File::open("tests/fixtures/rust.flif").unwrap();
+    let builder = flif::dec::decode(file).unwrap();
+    assert!(builder.info().metadata().is_empty());
+}
+
+#[test]
+fn huge_images_are_refused_before_anything_is_allocated() {
+    // A gray image of 1x2^40 pixels, with nothing but filler after the header
+    let mut data = b"FLIF\x31\x31\x00\x9F\xFF\xFF\xFF\xFF\x7F".to_vec();
+    data.extend_from_slice(&[0xFF; 64]);
+
+    match flif::dec::decode(&data[..]) {
+        Err(flif::dec::Error::BufferSizeExceedsLimit) => {}
+        other => panic!("expected the image to exceed the limit, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn custom_bitchances_are_refused() {
+    // 0xFF: custom chances, with the flag for a custom table set
+    match flif::dec::decode(&gray(0xFF)[..]) {
+        Err(flif::dec::Error::CustomBitchance) => {}
+        other => panic!("expected custom bit chances to be refused, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn unreadable_exif_fails_only_if_orientation_is_applied() {
+    // An EXIF chunk that inflates to "dummy", which isn't TIFF
+    let mut data = gray(0x3F);
+    data.splice(8..8, b"eXif\x07\x4b\x29\xcd\xcd\xad\x04\x00".iter().cloned());
+
+    let builder = flif::dec::decode(&data[..]).unwrap();
+    assert_eq!(builder.info().orientation(), flif::exif::Orientation::Normal);
+
+    let options = flif::dec::DecoderOptions { apply_orientation: true, ..Default::default() };
+    match flif::dec::decode_with_options(&data[..], options) {
+        Err(flif::dec::Error::Exif(_)) => {}
+        other => panic!("expected the EXIF to be unreadable, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn inflating_exif_for_the_orientation_counts_against_the_total_limit() {
+    // The 7 bytes of the compressed chunk are kept, "dummy" doesn't fit next to them
+    let mut data = gray(0x3F);
+    data.splice(8..8, b"eXif\x07\x4b\x29\xcd\xcd\xad\x04\x00".iter().cloned());
+
+    let options = flif::dec::DecoderOptions {
+        metadata: flif::metadata::MetadataOptions::all(flif::metadata::ChunkMode::Compressed),
+        max_metadata_size: 10,
+        apply_orientation: true,
+        ..Default::default()
+    };
+    match flif::dec::decode_with_options(&data[..], options) {
+        Err(flif::dec::Error::Metadata(flif::metadata::Error::TotalLimitExceeded(10))) => {}
+        other => panic!("expected the total limit to be hit, got {:?}", other.err()),
+    }
+}
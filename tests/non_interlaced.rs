@@ -0,0 +1,59 @@
+extern crate flif;
+
+use flif::dec::Progress;
+
+/// Builds a non-interlaced 4x3 image without metadata.
+///
+/// There is no encoder to produce real test files, but the range coder reads
+/// a 0 bit while its input is below the midpoint of the current range and a 1
+/// bit while it is at the very top of it. After `first`, the 0xFF bytes keep it
+/// there, so every following bit is 1: the trees are single leaves and every
+/// pixel equals its prediction.
+fn flif(format: u8, first: u8) -> Vec<u8> {
+    let mut data = b"FLIF".to_vec();
+    data.extend_from_slice(&[format, b'1', 3, 2, 0, first]);
+    data.extend_from_slice(&[0xFF; 64]);
+    data
+}
+
+fn decode(data: &[u8]) -> (Vec<flif::Image>, Progress) {
+    let builder = flif::dec::decode(data).unwrap();
+    flif::dec::decode_image_with_progress(builder, Default::default()).unwrap()
+}
+
+#[test]
+fn gray_image_is_predicted_black() {
+    // 0x3F: no custom chances, no transformations
+    let (images, progress) = decode(&flif(0x31, 0x3F));
+
+    assert_eq!(progress, Progress::Complete);
+    assert_eq!(images.len(), 1);
+    assert_eq!((images[0].width(), images[0].height()), (4, 3));
+    assert_eq!(images[0].to_u8().as_slice(), &[0; 12][..]);
+}
+
+#[test]
+fn rgba_image_is_predicted_black() {
+    // 0x1F: no zero alpha, no custom chances, no transformations
+    let (images, progress) = decode(&flif(0x34, 0x1F));
+
+    assert_eq!(progress, Progress::Complete);
+    assert_eq!(images[0].num_planes(), 4);
+    assert_eq!(images[0].to_u8().as_slice(), &[0; 48][..]);
+}
+
+#[test]
+fn truncated_image_reports_where_it_ended() {
+    let mut data = flif(0x31, 0x3F);
+    data.truncate(13);
+
+    let options = flif::dec::DecoderOptions { allow_truncated: true, ..Default::default() };
+    let builder = flif::dec::decode(&data[..]).unwrap();
+    let (images, progress) = flif::dec::decode_image_with_progress(builder, options).unwrap();
+
+    assert_eq!(progress, Progress::Incomplete { plane: 0, row: 2 });
+    assert_eq!(images[0].to_u8().as_slice(), &[0; 12][..]);
+
+    let builder = flif::dec::decode(&data[..]).unwrap();
+    assert!(flif::dec::decode_image(builder, Default::default()).is_err());
+}